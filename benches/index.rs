@@ -0,0 +1,112 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use mdb::db::Database;
+use mdb::extractor::{ExtractOptions, Extractor};
+use mdb::scanner::{self, IndexOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NOTES: usize = 2000;
+
+fn note(i: usize) -> String {
+    format!(
+        "---\ntags: [bench, group{}]\nstatus: draft\n---\n# Note {}\n\n\
+         Links to [[note-{}]] and [[note-{}#Section]], with a #topic/{} tag.\n\n\
+         > [!note] Callout\n> Body with ==highlight== and a footnote[^1].\n\n\
+         ```rust\nfn main() {{}}\n```\n\nowner:: someone\n\n[^1]: The footnote.\n",
+        i % 10,
+        i,
+        (i + 1) % NOTES,
+        (i + 7) % NOTES,
+        i % 25
+    )
+}
+
+/// A vault of `NOTES` notes spread over ten folders.
+fn create_vault() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mdb_bench_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for i in 0..NOTES {
+        let folder = dir.join(format!("folder-{}", i % 10));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join(format!("note-{}.md", i)), note(i)).unwrap();
+    }
+    dir
+}
+
+fn remove_db(path: &Path) {
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(path.with_extension("duckdb.wal"));
+}
+
+fn bench_extract(c: &mut Criterion) {
+    let content = note(1);
+    let options = ExtractOptions::default();
+    c.bench_function("extract", |b| {
+        b.iter(|| Extractor::extract(&content, &options))
+    });
+}
+
+/// One worker, four, and one per core.
+fn job_counts() -> Vec<usize> {
+    let mut jobs = vec![1, 4, scanner::default_jobs()];
+    jobs.sort();
+    jobs.dedup();
+    jobs
+}
+
+/// Full index of a fresh database.
+fn bench_index(c: &mut Criterion) {
+    let vault = create_vault();
+    let db_path = vault.with_extension("duckdb");
+    let options = ExtractOptions::default();
+
+    let mut group = c.benchmark_group("index");
+    group.sample_size(10);
+    for jobs in job_counts() {
+        let index = IndexOptions {
+            jobs,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("jobs", jobs), &index, |b, index| {
+            b.iter(|| {
+                remove_db(&db_path);
+                let db = Database::new(&db_path).unwrap();
+                scanner::index_directory(&vault, &db, index, &options).unwrap();
+            })
+        });
+    }
+    group.finish();
+
+    remove_db(&db_path);
+    let _ = fs::remove_dir_all(&vault);
+}
+
+/// Index of a vault already indexed and unchanged, which is walking the
+/// tree and comparing each file's size and mtime with the index.
+fn bench_reindex(c: &mut Criterion) {
+    let vault = create_vault();
+    let db_path = vault.with_extension("duckdb");
+    let options = ExtractOptions::default();
+    remove_db(&db_path);
+    let db = Database::new(&db_path).unwrap();
+    scanner::index_directory(&vault, &db, &IndexOptions::default(), &options).unwrap();
+
+    let mut group = c.benchmark_group("reindex");
+    for jobs in job_counts() {
+        let index = IndexOptions {
+            jobs,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("jobs", jobs), &index, |b, index| {
+            b.iter(|| scanner::index_directory(&vault, &db, index, &options).unwrap())
+        });
+    }
+    group.finish();
+
+    drop(db);
+    remove_db(&db_path);
+    let _ = fs::remove_dir_all(&vault);
+}
+
+criterion_group!(benches, bench_extract, bench_index, bench_reindex);
+criterion_main!(benches);
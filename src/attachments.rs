@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// File extensions indexed as attachments, with the mime type stored for
+/// each.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("pdf", "application/pdf"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("m4a", "audio/mp4"),
+    ("ogg", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("3gp", "audio/3gpp"),
+];
+
+/// What is stored about a non-markdown file besides its `documents` row.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub mime: String,
+    /// Pixel size, for raster images whose header could be read.
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+}
+
+impl Attachment {
+    /// Describes the attachment at `path` from its extension and, for
+    /// images, the header, or `None` when the extension is not an
+    /// attachment type.
+    pub fn read(ext: &str, path: &Path) -> Option<Self> {
+        let mime = mime_type(ext)?;
+        let (width, height) = if mime.starts_with("image/") {
+            imagesize::size(path)
+                .map(|size| (Some(size.width as u64), Some(size.height as u64)))
+                .unwrap_or((None, None))
+        } else {
+            (None, None)
+        };
+        Some(Attachment {
+            mime: mime.to_string(),
+            width,
+            height,
+        })
+    }
+}
+
+/// The mime type of an attachment extension, matched case-insensitively.
+pub fn mime_type(ext: &str) -> Option<&'static str> {
+    let ext = ext.to_ascii_lowercase();
+    MIME_TYPES
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// The `content_hash` of the file at `path`, read a chunk at a time so a
+/// large attachment is never held in memory whole.
+pub fn file_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 PNG: signature and IHDR chunk, which is all the size needs.
+    const PNG: &[u8] = &[
+        0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, b'I', b'H', b'D',
+        b'R', 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type("png"), Some("image/png"));
+        assert_eq!(mime_type("JPG"), Some("image/jpeg"));
+        assert_eq!(mime_type("pdf"), Some("application/pdf"));
+        assert_eq!(mime_type("md"), None);
+    }
+
+    fn write_temp(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("test_attachment_{}_{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_image_dimensions() {
+        let png = write_temp("image.png", PNG);
+        let attachment = Attachment::read("png", &png).unwrap();
+        assert_eq!(attachment.mime, "image/png");
+        assert_eq!(attachment.width, Some(3));
+        assert_eq!(attachment.height, Some(2));
+
+        let not_png = write_temp("not.png", b"not a png");
+        let attachment = Attachment::read("png", &not_png).unwrap();
+        assert_eq!(attachment.width, None);
+        assert!(Attachment::read("txt", &not_png).is_none());
+        assert!(Attachment::read("mp4", &not_png).is_none());
+
+        let _ = std::fs::remove_file(png);
+        let _ = std::fs::remove_file(not_png);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(content_hash(b"abc"), abc);
+
+        let path = write_temp("abc", b"abc");
+        assert_eq!(file_hash(&path).unwrap(), abc);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+
+/// An Obsidian canvas: a JSON graph of cards and the edges between them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Canvas {
+    #[serde(default)]
+    pub nodes: Vec<CanvasNode>,
+    #[serde(default)]
+    pub edges: Vec<CanvasEdge>,
+}
+
+/// A canvas card. Which optional fields are set depends on `kind`: `text`
+/// cards have `text`, `file` cards `file` (and maybe `subpath`), `link`
+/// cards `url`, and `group` cards `label`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanvasNode {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: Option<String>,
+    pub file: Option<String>,
+    pub subpath: Option<String>,
+    pub url: Option<String>,
+    pub label: Option<String>,
+    #[serde(default)]
+    pub x: f64,
+    #[serde(default)]
+    pub y: f64,
+    #[serde(default)]
+    pub width: f64,
+    #[serde(default)]
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanvasEdge {
+    pub id: String,
+    #[serde(rename = "fromNode")]
+    pub from_node: String,
+    #[serde(rename = "toNode")]
+    pub to_node: String,
+    pub label: Option<String>,
+}
+
+impl Canvas {
+    pub fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// The text cards joined as one markdown document, so tags, links and
+    /// the other extracted fields come from the regular extractor.
+    pub fn markdown(&self) -> String {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.text.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Links for the file cards, by the vault path a card already holds,
+    /// without `.md` for notes, plus any subpath. A bare file name would not
+    /// tell apart files of the same name in different folders.
+    pub fn file_links(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter_map(|node| {
+                let file = node.file.as_deref()?;
+                let target = file.strip_suffix(".md").unwrap_or(file);
+                let subpath = node.subpath.as_deref().unwrap_or("");
+                Some(format!("{}{}", target, subpath))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANVAS: &str = r##"{
+        "nodes": [
+            {"id": "a", "type": "text", "text": "# Plan\nShip #q3 with [[Roadmap]]", "x": 0, "y": 0, "width": 250, "height": 60},
+            {"id": "b", "type": "file", "file": "Projects/Spec.md", "subpath": "#Scope", "x": 300, "y": 0, "width": 400, "height": 400},
+            {"id": "c", "type": "file", "file": "assets/diagram.png", "x": 0, "y": 300, "width": 200, "height": 200},
+            {"id": "d", "type": "link", "url": "https://example.com", "x": 0.5, "y": 600, "width": 200, "height": 100.25},
+            {"id": "e", "type": "group", "label": "Q3", "x": -50, "y": -50, "width": 900, "height": 900}
+        ],
+        "edges": [
+            {"id": "e1", "fromNode": "a", "fromSide": "right", "toNode": "b", "toSide": "left", "label": "details"}
+        ]
+    }"##;
+
+    #[test]
+    fn test_parse_canvas() {
+        let canvas = Canvas::parse(CANVAS).unwrap();
+        assert_eq!(canvas.nodes.len(), 5);
+        assert_eq!(canvas.nodes[1].kind, "file");
+        assert_eq!(canvas.nodes[4].label.as_deref(), Some("Q3"));
+        assert_eq!(canvas.nodes[3].x, 0.5);
+        assert_eq!(canvas.nodes[3].height, 100.25);
+        assert_eq!(canvas.nodes[4].x, -50.0);
+        assert_eq!(
+            canvas.edges,
+            vec![CanvasEdge {
+                id: "e1".to_string(),
+                from_node: "a".to_string(),
+                to_node: "b".to_string(),
+                label: Some("details".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_canvas_markdown_and_links() {
+        let canvas = Canvas::parse(CANVAS).unwrap();
+        assert_eq!(canvas.markdown(), "# Plan\nShip #q3 with [[Roadmap]]");
+        assert_eq!(
+            canvas.file_links(),
+            vec!["Projects/Spec#Scope", "assets/diagram.png"]
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_and_invalid_canvas() {
+        assert_eq!(Canvas::parse("{}").unwrap(), Canvas::default());
+        assert!(Canvas::parse("{\"nodes\": [{}]}").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::dates::PeriodicFormats;
+use crate::extractor::{FieldExtractor, RegexExtractor};
+use crate::query::compiler::FILE_FIELDS;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Per-vault settings, read from `.mdb/config.toml` at the vault root.
+///
+/// ```toml
+/// [[extractors]]
+/// name = "tickets"
+/// pattern = '\b[A-Z]+-\d+\b'
+///
+/// [[extractors]]
+/// name = "mentions"
+/// pattern = '@(\w+)'
+///
+/// [dates]
+/// daily = ["%Y-%m-%d", "Daily %d.%m.%Y"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub extractors: Vec<ExtractorConfig>,
+    #[serde(default)]
+    pub dates: PeriodicFormats,
+}
+
+/// A regex extractor whose matches are stored in a list column called `name`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractorConfig {
+    pub name: String,
+    pub pattern: String,
+    /// Capture group to keep; defaults to the first group, or the whole
+    /// match when the pattern has none.
+    pub group: Option<usize>,
+}
+
+impl Config {
+    pub const FILE: &'static str = ".mdb/config.toml";
+
+    /// Loads the config of the vault at `root`, or the defaults when the
+    /// file does not exist.
+    pub fn load(root: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = root.join(Self::FILE);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(&path)?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        for (i, extractor) in config.extractors.iter().enumerate() {
+            validate_column_name(&extractor.name)?;
+            if config.extractors[..i]
+                .iter()
+                .any(|e| e.name == extractor.name)
+            {
+                return Err(format!("extractor '{}' is declared twice", extractor.name));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn field_extractors(&self) -> Result<Vec<Arc<dyn FieldExtractor>>, String> {
+        self.extractors
+            .iter()
+            .map(|e| {
+                RegexExtractor::new(&e.name, &e.pattern, e.group)
+                    .map(|extractor| Arc::new(extractor) as Arc<dyn FieldExtractor>)
+            })
+            .collect()
+    }
+}
+
+/// Extractor names become `documents` columns, so they must be plain
+/// lowercase identifiers that do not shadow a built-in column.
+fn validate_column_name(name: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(format!(
+            "extractor name '{}' must use only a-z, 0-9 and _",
+            name
+        ));
+    }
+    if FILE_FIELDS.contains(&name) || name == "properties" {
+        return Err(format!(
+            "extractor name '{}' clashes with a built-in column",
+            name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_extractors() {
+        let config = Config::parse(
+            r#"
+[[extractors]]
+name = "tickets"
+pattern = '\b[A-Z]+-\d+\b'
+
+[[extractors]]
+name = "mentions"
+pattern = '@(\w+)'
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.extractors.len(), 2);
+        assert_eq!(config.extractors[1].name, "mentions");
+        assert_eq!(config.extractors[1].group, None);
+
+        let extractors = config.field_extractors().unwrap();
+        assert_eq!(
+            extractors[0].extract("Fixes JIRA-1234 and OPS-7"),
+            vec!["JIRA-1234", "OPS-7"]
+        );
+        assert_eq!(extractors[1].extract("ask @ana and @bo"), vec!["ana", "bo"]);
+    }
+
+    #[test]
+    fn test_parse_empty_config() {
+        let config = Config::parse("").unwrap();
+        assert!(config.extractors.is_empty());
+        assert_eq!(config.dates.daily, vec!["%Y-%m-%d"]);
+    }
+
+    #[test]
+    fn test_parse_date_formats() {
+        let config = Config::parse("[dates]\ndaily = [\"Daily %d.%m.%Y\"]\n").unwrap();
+        assert_eq!(config.dates.daily, vec!["Daily %d.%m.%Y"]);
+        assert_eq!(config.dates.weekly, vec!["%G-W%V"]);
+        assert!(Config::parse("[dates]\nyearly = [\"%Y\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_names() {
+        let config = |name: &str| format!("[[extractors]]\nname = \"{}\"\npattern = 'x'\n", name);
+        assert!(Config::parse(&config("tags")).is_err());
+        assert!(Config::parse(&config("Tickets")).is_err());
+        assert!(Config::parse(&config("my-field")).is_err());
+        assert!(Config::parse(&format!("{}{}", config("a"), config("a"))).is_err());
+        assert!(Config::parse(&config("due_2")).is_ok());
+    }
+
+    #[test]
+    fn test_field_extractors_reject_bad_patterns() {
+        let config = Config::parse("[[extractors]]\nname = \"x\"\npattern = '('\n").unwrap();
+        assert!(config.field_extractors().is_err());
+
+        let config =
+            Config::parse("[[extractors]]\nname = \"x\"\npattern = 'a(b)'\ngroup = 2\n").unwrap();
+        assert!(config.field_extractors().is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Frontmatter keys holding a note's date, in order of preference.
+const FRONTMATTER_DATE_KEYS: &[&str] = &["date", "created"];
+
+/// chrono formats matched against the whole file name (without `.md`) of
+/// periodic notes. Weekly notes resolve to the Monday of the ISO week and
+/// monthly notes to the first of the month.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeriodicFormats {
+    pub daily: Vec<String>,
+    pub weekly: Vec<String>,
+    pub monthly: Vec<String>,
+}
+
+impl Default for PeriodicFormats {
+    fn default() -> Self {
+        PeriodicFormats {
+            daily: vec!["%Y-%m-%d".to_string()],
+            weekly: vec!["%G-W%V".to_string()],
+            monthly: vec!["%Y-%m".to_string()],
+        }
+    }
+}
+
+/// Where `file.date` came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateSource {
+    Filename,
+    Frontmatter,
+    Filesystem,
+}
+
+impl DateSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateSource::Filename => "filename",
+            DateSource::Frontmatter => "frontmatter",
+            DateSource::Filesystem => "filesystem",
+        }
+    }
+}
+
+/// Picks a note's date from its periodic file name, then its `date` or
+/// `created` property, and finally its creation time on disk.
+pub fn infer_date(
+    name: &str,
+    properties: &Value,
+    ctime: i64,
+    formats: &PeriodicFormats,
+) -> (NaiveDate, DateSource) {
+    if let Some(date) = date_from_filename(name, formats) {
+        return (date, DateSource::Filename);
+    }
+    if let Some(date) = date_from_properties(properties) {
+        return (date, DateSource::Frontmatter);
+    }
+    let date = DateTime::from_timestamp(ctime, 0)
+        .unwrap_or_default()
+        .date_naive();
+    (date, DateSource::Filesystem)
+}
+
+pub fn date_from_filename(name: &str, formats: &PeriodicFormats) -> Option<NaiveDate> {
+    // chrono needs a full date, so weeks and months are completed with
+    // their first day before parsing.
+    let daily = formats
+        .daily
+        .iter()
+        .find_map(|f| NaiveDate::parse_from_str(name, f).ok());
+    let with_first_day = |patterns: &[String], day: &str, day_format: &str| {
+        let name = format!("{} {}", name, day);
+        patterns
+            .iter()
+            .find_map(|f| NaiveDate::parse_from_str(&name, &format!("{} {}", f, day_format)).ok())
+    };
+    daily
+        .or_else(|| with_first_day(&formats.weekly, "1", "%u"))
+        .or_else(|| with_first_day(&formats.monthly, "01", "%d"))
+}
+
+/// Accepts dates and datetimes (`2024-01-15`, `2024-01-15T09:30`,
+/// `2024-01-15 09:30:00`) by their leading date part. A key set in both the
+/// frontmatter and an inline field holds an array; its first string is used.
+fn date_from_properties(properties: &Value) -> Option<NaiveDate> {
+    FRONTMATTER_DATE_KEYS.iter().find_map(|key| {
+        let value = properties.get(key)?;
+        let text = match value {
+            Value::Array(items) => items.iter().find_map(Value::as_str)?,
+            _ => value.as_str()?,
+        }
+        .trim();
+        text.get(..10)
+            .and_then(|prefix| NaiveDate::parse_from_str(prefix, "%Y-%m-%d").ok())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_date_from_periodic_filenames() {
+        let formats = PeriodicFormats::default();
+        assert_eq!(
+            date_from_filename("2024-01-15", &formats),
+            Some(ymd(2024, 1, 15))
+        );
+        assert_eq!(
+            date_from_filename("2024-W03", &formats),
+            Some(ymd(2024, 1, 15))
+        );
+        assert_eq!(
+            date_from_filename("2024-02", &formats),
+            Some(ymd(2024, 2, 1))
+        );
+        assert_eq!(date_from_filename("2024-13-01", &formats), None);
+        assert_eq!(date_from_filename("meeting notes", &formats), None);
+    }
+
+    #[test]
+    fn test_date_from_custom_formats() {
+        let formats = PeriodicFormats {
+            daily: vec!["Daily %d.%m.%Y".to_string()],
+            weekly: vec![],
+            monthly: vec!["%B %Y".to_string()],
+        };
+        assert_eq!(
+            date_from_filename("Daily 15.01.2024", &formats),
+            Some(ymd(2024, 1, 15))
+        );
+        assert_eq!(
+            date_from_filename("March 2024", &formats),
+            Some(ymd(2024, 3, 1))
+        );
+        assert_eq!(date_from_filename("2024-01-15", &formats), None);
+    }
+
+    #[test]
+    fn test_infer_date_order() {
+        let formats = PeriodicFormats::default();
+        let properties = json!({"created": "2023-05-01T10:00:00", "date": "2023-06-02"});
+        let ctime = 1_700_000_000; // 2023-11-14
+
+        assert_eq!(
+            infer_date("2024-01-15", &properties, ctime, &formats),
+            (ymd(2024, 1, 15), DateSource::Filename)
+        );
+        assert_eq!(
+            infer_date("Trip", &properties, ctime, &formats),
+            (ymd(2023, 6, 2), DateSource::Frontmatter)
+        );
+        assert_eq!(
+            infer_date(
+                "Trip",
+                &json!({"created": "2023-05-01 10:00"}),
+                ctime,
+                &formats
+            ),
+            (ymd(2023, 5, 1), DateSource::Frontmatter)
+        );
+        assert_eq!(
+            infer_date("Trip", &json!({"date": "someday"}), ctime, &formats),
+            (ymd(2023, 11, 14), DateSource::Filesystem)
+        );
+        assert_eq!(
+            infer_date(
+                "Trip",
+                &json!({"date": ["2023-07-03", "2023-07-04"]}),
+                ctime,
+                &formats
+            ),
+            (ymd(2023, 7, 3), DateSource::Frontmatter)
+        );
+    }
+}
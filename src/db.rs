@@ -1,9 +1,21 @@
-use duckdb::{params, Connection};
+use crate::attachments::Attachment;
+use crate::canvas::{CanvasEdge, CanvasNode};
+use crate::dates::DateSource;
+use crate::extractor::{
+    Block, Callout, CodeBlock, ExtractError, Footnote, FrontmatterFormat, Source,
+};
+use crate::vault::Vault;
+use duckdb::{Appender, Connection, ToSql, appender_params_from_iter, params, params_from_iter};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
+    /// Name of the vault the file is in; `path` is relative to its root.
+    pub vault: String,
     pub path: String,
     pub folder: String,
     pub name: String,
@@ -11,16 +23,97 @@ pub struct Document {
     pub size: u64,
     pub ctime: i64,
     pub mtime: i64,
-    pub content: String,
+    /// Hex SHA-256 of the file, used to tell changed and moved files apart.
+    pub hash: String,
+    /// Note date inferred by `dates::infer_date`.
+    pub date: chrono::NaiveDate,
+    pub date_source: DateSource,
+    /// The raw file, `None` when indexed with `skip_content`.
+    pub content: Option<String>,
+    pub body: String,
+    pub plain_text: String,
     pub tags: Vec<String>,
+    pub tag_sources: BTreeMap<String, Vec<Source>>,
+    pub aliases: Vec<String>,
     pub links: Vec<String>,
     pub backlinks: Vec<String>,
     pub embeds: Vec<String>,
+    pub highlights: Vec<String>,
+    pub footnotes: Vec<Footnote>,
+    pub comments: Vec<String>,
+    /// Values from configured extractors; each key is its own list column.
+    pub custom_fields: BTreeMap<String, Vec<String>>,
     pub properties: serde_json::Value,
+    pub frontmatter_format: Option<FrontmatterFormat>,
+    pub errors: Vec<ExtractError>,
+    pub property_sources: BTreeMap<String, Vec<Source>>,
+    pub blocks: Vec<Block>,
+    pub callouts: Vec<Callout>,
+    pub code_blocks: Vec<CodeBlock>,
+    pub canvas_nodes: Vec<CanvasNode>,
+    pub canvas_edges: Vec<CanvasEdge>,
+    /// Set for images, PDFs and other non-markdown files.
+    pub attachment: Option<Attachment>,
+    pub words: u64,
+    pub chars: u64,
+    pub lines: u64,
+    pub reading_time: u64,
+    pub title: Option<String>,
+    pub excerpt: String,
 }
 
+/// Tables with a row per file or per item extracted from it, keyed by
+/// `vault` and `path`. `documents` comes last.
+const DOCUMENT_TABLES: &[&str] = &[
+    "blocks",
+    "callouts",
+    "code_blocks",
+    "canvas_nodes",
+    "canvas_edges",
+    "attachments",
+    "documents",
+];
+
+/// Items found in files, each with the vault and path of its file.
+pub type FileItems<T> = Vec<(String, String, T)>;
+
+/// What the index knows about a file to tell whether it changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub hash: String,
+    pub mtime: i64,
+    pub size: u64,
+}
+
+/// The version of the tables below, kept in `metadata` and raised with
+/// every change to them:
+///
+/// 1. `tag_sources` and `aliases`
+/// 2. `property_sources`
+/// 3. `blocks`
+/// 4. `words`, `chars`, `lines`, `reading_time`, `title` and `excerpt`
+/// 5. `frontmatter_format`
+/// 6. `errors`
+/// 7. `callouts` and `callout_types`
+/// 8. `code_blocks` and `code_languages`
+/// 9. `highlights`, `footnotes` and `comments`
+/// 10. `date` and `date_source`
+/// 11. `body` and `plain_text`
+/// 12. `canvas_nodes` and `canvas_edges`
+/// 13. `attachments`
+/// 14. `hash`
+/// 15. paths relative to the vault root
+/// 16. `vaults`, and files keyed by vault as well as path
+/// 17. `size` as `BIGINT`, for attachments of 2 GiB and more
+///
+/// A database of an older version, or from before versions were kept, is
+/// emptied and rebuilt by `init_schema`, since the key change of version 16
+/// and the type change of version 17 cannot be made to an existing table.
+const SCHEMA_VERSION: i64 = 17;
+
 pub struct Database {
     conn: Connection,
+    rebuilt: bool,
 }
 
 impl Database {
@@ -29,27 +122,197 @@ impl Database {
             std::fs::create_dir_all(parent)?;
         }
         let conn = Connection::open(path)?;
-        let db = Database { conn };
-        db.init_schema()?;
+        let mut db = Database {
+            conn,
+            rebuilt: false,
+        };
+        db.rebuilt = db.init_schema()?;
         Ok(db)
     }
 
-    fn init_schema(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Whether the database was made by an older version whose tables
+    /// could not be brought up to date, and was emptied, so it has to be
+    /// indexed again.
+    pub fn rebuilt(&self) -> bool {
+        self.rebuilt
+    }
+
+    /// The schema version the database was made with: `None` for a new
+    /// one, 0 for one from before versions were kept.
+    fn schema_version(&self) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT value FROM metadata WHERE key = 'schema_version'")?;
+        let mut rows = stmt.query([])?;
+        if let Some(row) = rows.next()? {
+            let version: String = row.get(0)?;
+            return Ok(Some(version.parse()?));
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT count(*) FROM duckdb_tables() WHERE table_name = 'documents'")?;
+        let tables: i64 = stmt.query_row([], |row| row.get(0))?;
+        Ok((tables > 0).then_some(0))
+    }
+
+    /// Creates the tables, emptying those of an older version first.
+    /// Returns whether it did.
+    fn init_schema(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS metadata (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+            [],
+        )?;
+        let version = self.schema_version()?;
+        if let Some(version) = version
+            && version > SCHEMA_VERSION
+        {
+            return Err(format!(
+                "the database has schema version {}, newer than the {} this mdb supports; upgrade mdb",
+                version, SCHEMA_VERSION
+            )
+            .into());
+        }
+        let rebuilt = version.is_some_and(|version| version < SCHEMA_VERSION);
+        if rebuilt {
+            for table in DOCUMENT_TABLES.iter().chain(&["vaults"]) {
+                self.conn
+                    .execute(&format!("DROP TABLE IF EXISTS {}", table), [])?;
+            }
+        }
+
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS documents (
-                path TEXT PRIMARY KEY,
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
                 folder TEXT NOT NULL,
                 name TEXT NOT NULL,
                 ext TEXT NOT NULL,
-                size INTEGER NOT NULL,
+                size BIGINT NOT NULL,
                 ctime TIMESTAMPTZ NOT NULL,
                 mtime TIMESTAMPTZ NOT NULL,
                 content TEXT,
                 tags VARCHAR[],
+                tag_sources JSON,
+                aliases VARCHAR[],
                 links VARCHAR[],
                 backlinks VARCHAR[],
                 embeds VARCHAR[],
-                properties JSON
+                properties JSON,
+                property_sources JSON,
+                words INTEGER,
+                chars INTEGER,
+                lines INTEGER,
+                reading_time INTEGER,
+                title TEXT,
+                excerpt TEXT,
+                frontmatter_format TEXT,
+                errors JSON,
+                callout_types VARCHAR[],
+                code_languages VARCHAR[],
+                highlights VARCHAR[],
+                footnotes JSON,
+                comments VARCHAR[],
+                date DATE,
+                date_source TEXT,
+                body TEXT,
+                plain_text TEXT,
+                hash TEXT,
+                PRIMARY KEY (vault, path)
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS blocks (
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
+                block_id TEXT NOT NULL,
+                text TEXT,
+                start_line INTEGER NOT NULL,
+                end_line INTEGER NOT NULL,
+                PRIMARY KEY (vault, path, block_id)
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS callouts (
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
+                idx INTEGER NOT NULL,
+                callout_type TEXT NOT NULL,
+                title TEXT,
+                fold TEXT,
+                body TEXT,
+                depth INTEGER NOT NULL,
+                parent INTEGER,
+                start_line INTEGER NOT NULL,
+                end_line INTEGER NOT NULL,
+                PRIMARY KEY (vault, path, idx)
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS code_blocks (
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
+                idx INTEGER NOT NULL,
+                language TEXT,
+                info TEXT,
+                text TEXT,
+                start_line INTEGER NOT NULL,
+                end_line INTEGER NOT NULL,
+                PRIMARY KEY (vault, path, idx)
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS canvas_nodes (
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
+                node_id TEXT NOT NULL,
+                node_type TEXT NOT NULL,
+                text TEXT,
+                file TEXT,
+                subpath TEXT,
+                url TEXT,
+                label TEXT,
+                x DOUBLE,
+                y DOUBLE,
+                width DOUBLE,
+                height DOUBLE,
+                PRIMARY KEY (vault, path, node_id)
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS canvas_edges (
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
+                edge_id TEXT NOT NULL,
+                from_node TEXT NOT NULL,
+                to_node TEXT NOT NULL,
+                label TEXT,
+                PRIMARY KEY (vault, path, edge_id)
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS attachments (
+                vault TEXT NOT NULL,
+                path TEXT NOT NULL,
+                mime TEXT NOT NULL,
+                width BIGINT,
+                height BIGINT,
+                PRIMARY KEY (vault, path)
             )",
             [],
         )?;
@@ -65,41 +328,296 @@ impl Database {
         self.conn
             .execute("CREATE INDEX IF NOT EXISTS idx_name ON documents(name)", [])?;
 
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS vaults (
+                name TEXT PRIMARY KEY,
+                root TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        self.conn.execute(
+            "INSERT OR REPLACE INTO metadata VALUES ('schema_version', ?)",
+            params![SCHEMA_VERSION.to_string()],
+        )?;
+
+        Ok(rebuilt)
+    }
+
+    /// Runs `sql` as it is, for tests that need the tables in a given state.
+    #[cfg(test)]
+    pub(crate) fn execute_batch(&self, sql: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute_batch(sql)?;
         Ok(())
     }
 
-    pub fn upsert_document(&self, doc: &Document) -> Result<(), Box<dyn std::error::Error>> {
-        let ctime_dt = chrono::DateTime::from_timestamp(doc.ctime, 0).unwrap();
-        let mtime_dt = chrono::DateTime::from_timestamp(doc.mtime, 0).unwrap();
+    /// Every vault indexed into the database, by name.
+    pub fn get_vaults(&self) -> Result<Vec<Vault>, Box<dyn std::error::Error>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, root FROM vaults ORDER BY name")?;
+        let mut rows = stmt.query([])?;
+
+        let mut vaults = Vec::new();
+        while let Some(row) = rows.next()? {
+            let root: String = row.get(1)?;
+            vaults.push(Vault {
+                name: row.get(0)?,
+                root: PathBuf::from(root),
+            });
+        }
+
+        Ok(vaults)
+    }
 
+    /// Registers a vault, or moves a registered one to a new root.
+    pub fn set_vault(&self, vault: &Vault) -> Result<(), Box<dyn std::error::Error>> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO documents 
-             (path, folder, name, ext, size, ctime, mtime, content, tags, links, backlinks, embeds, properties)
-             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-            params![
-                &doc.path,
-                &doc.folder,
-                &doc.name,
-                &doc.ext,
-                doc.size as i64,
-                ctime_dt,
-                mtime_dt,
-                &doc.content,
-                serde_json::to_string(&doc.tags)?,
-                serde_json::to_string(&doc.links)?,
-                serde_json::to_string(&doc.backlinks)?,
-                serde_json::to_string(&doc.embeds)?,
-                serde_json::to_string(&doc.properties)?,
-            ],
+            "INSERT OR REPLACE INTO vaults VALUES (?, ?)",
+            params![vault.name, vault.root.to_string_lossy().to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Writes files that are not indexed yet in one transaction, through
+    /// DuckDB's Appender rather than a statement per row. Used for fresh
+    /// indexes, where this is most of the writing.
+    pub fn insert_documents(&self, docs: &[Document]) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut appenders: HashMap<(&str, Vec<&str>), Appender> = HashMap::new();
+            for doc in docs {
+                for row in document_rows(doc)? {
+                    let appender = match appenders.entry((row.table, row.columns)) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            let (table, columns) = entry.key();
+                            let appender = self.conn.appender_with_columns(table, columns)?;
+                            entry.insert(appender)
+                        }
+                    };
+                    appender.append_row(appender_params_from_iter(&row.values))?;
+                }
+            }
+            for appender in appenders.values_mut() {
+                appender.flush()?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Writes files in one transaction, replacing whatever was stored for
+    /// their paths.
+    pub fn replace_documents(&self, docs: &[Document]) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        for doc in docs {
+            for table in DOCUMENT_TABLES {
+                tx.prepare_cached(&format!(
+                    "DELETE FROM {} WHERE vault = ? AND path = ?",
+                    table
+                ))?
+                .execute(params![&doc.vault, &doc.path])?;
+            }
+            for row in document_rows(doc)? {
+                let columns: Vec<String> =
+                    row.columns.iter().map(|c| format!("\"{}\"", c)).collect();
+                let sql = format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    row.table,
+                    columns.join(", "),
+                    vec!["?"; columns.len()].join(", ")
+                );
+                tx.prepare_cached(&sql)?
+                    .execute(params_from_iter(&row.values))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Sets the backlinks of each path in `vault` in one transaction,
+    /// leaving the rest of its row alone.
+    pub fn set_backlinks(
+        &self,
+        vault: &str,
+        backlinks: &[(String, Vec<String>)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let values = backlinks
+            .iter()
+            .map(|(path, sources)| Ok((path.as_str(), json(sources)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        self.update_column(vault, "backlinks", "VARCHAR[]", &values)
+    }
+
+    /// Sets `column`, of type `column_type`, of each path in `vault` in one
+    /// transaction. The values are appended to a temporary table and set by
+    /// a single UPDATE, since an UPDATE per row costs DuckDB 2-3 ms once
+    /// `documents` has its indexes, which made it most of a full index.
+    fn update_column(
+        &self,
+        vault: &str,
+        column: &str,
+        column_type: &str,
+        values: &[(&str, Box<dyn ToSql>)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute_batch(&format!(
+            "CREATE OR REPLACE TEMP TABLE column_values (path VARCHAR, value {})",
+            column_type
+        ))?;
+        {
+            let mut appender = tx.appender("column_values")?;
+            for (path, value) in values {
+                appender.append_row(params![path, value])?;
+            }
+            appender.flush()?;
+        }
+        tx.execute(
+            &format!(
+                "UPDATE documents SET {} = v.value FROM column_values v
+                 WHERE documents.vault = ? AND documents.path = v.path",
+                column
+            ),
+            params![vault],
+        )?;
+        tx.execute_batch("DROP TABLE column_values")?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Adds a `VARCHAR[]` column to `documents` for each custom extractor.
+    /// Names are validated by the config loader.
+    pub fn add_list_columns(&self, names: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        for name in names {
+            self.conn.execute(
+                &format!(
+                    "ALTER TABLE documents ADD COLUMN IF NOT EXISTS \"{}\" VARCHAR[]",
+                    name
+                ),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Paths of all indexed files inside `dir`, a folder relative to the
+    /// root of `vault`; `""` for all of them.
+    pub fn get_paths_under(
+        &self,
+        vault: &str,
+        dir: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let dir = dir.trim_end_matches('/');
+        let prefix = if dir.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", dir)
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT path FROM documents WHERE vault = ? AND starts_with(path, ?) ORDER BY path",
+        )?;
+        let mut rows = stmt.query(params![vault, prefix])?;
+
+        let mut paths = Vec::new();
+        while let Some(row) = rows.next()? {
+            paths.push(row.get(0)?);
+        }
+
+        Ok(paths)
+    }
+
+    /// Removes a file of `vault` with everything extracted from it, and
+    /// drops it from the backlinks of the notes it linked to, in one
+    /// transaction.
+    pub fn delete_document(
+        &self,
+        vault: &str,
+        path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        for table in DOCUMENT_TABLES {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE vault = ? AND path = ?", table),
+                params![vault, path],
+            )?;
+        }
+        tx.execute(
+            "UPDATE documents SET backlinks = list_filter(backlinks, b -> b <> ?2)
+             WHERE vault = ?1 AND list_contains(backlinks, ?2)",
+            params![vault, path],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Moves an indexed file to a new path in `vault`, keeping its rows, and
+    /// points the backlinks of other notes at the new path, in one
+    /// transaction.
+    pub fn rename_document(
+        &self,
+        vault: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.unchecked_transaction()?;
+        for table in DOCUMENT_TABLES {
+            tx.execute(
+                &format!(
+                    "UPDATE {} SET path = ?1 WHERE vault = ?3 AND path = ?2",
+                    table
+                ),
+                params![new, old, vault],
+            )?;
+        }
+        tx.execute(
+            "UPDATE documents
+             SET backlinks = list_transform(backlinks, b -> CASE WHEN b = ?2 THEN ?1 ELSE b END)
+             WHERE vault = ?3 AND list_contains(backlinks, ?2)",
+            params![new, old, vault],
         )?;
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn get_mtime(&self, path: &str) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    /// Returns the content hash and mtime of every file indexed in `vault`
+    /// by path.
+    pub fn get_file_states(
+        &self,
+        vault: &str,
+    ) -> Result<HashMap<String, FileState>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, coalesce(hash, ''), mtime, size FROM documents WHERE vault = ?",
+        )?;
+        let mut rows = stmt.query(params![vault])?;
+
+        let mut states = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let mtime: chrono::DateTime<chrono::Utc> = row.get(2)?;
+            let size: i64 = row.get(3)?;
+            let state = FileState {
+                hash: row.get(1)?,
+                mtime: mtime.timestamp(),
+                size: size as u64,
+            };
+            states.insert(row.get(0)?, state);
+        }
+
+        Ok(states)
+    }
+
+    // Part of the library API; the binary reads mtimes in bulk through
+    // `get_file_states`.
+    #[allow(dead_code)]
+    pub fn get_mtime(
+        &self,
+        vault: &str,
+        path: &str,
+    ) -> Result<Option<i64>, Box<dyn std::error::Error>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT mtime FROM documents WHERE path = ?")?;
-        let mut rows = stmt.query(params![path])?;
+            .prepare("SELECT mtime FROM documents WHERE vault = ? AND path = ?")?;
+        let mut rows = stmt.query(params![vault, path])?;
 
         if let Some(row) = rows.next()? {
             let mtime: chrono::DateTime<chrono::Utc> = row.get(0)?;
@@ -109,13 +627,32 @@ impl Database {
         }
     }
 
+    /// Sets the mtime of each path in `vault` in one transaction, for files
+    /// touched without their content changing.
+    pub fn update_mtimes(
+        &self,
+        vault: &str,
+        mtimes: &[(String, i64)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let values: Vec<(&str, Box<dyn ToSql>)> = mtimes
+            .iter()
+            .map(|(path, mtime)| {
+                let mtime_dt = chrono::DateTime::from_timestamp(*mtime, 0).unwrap();
+                (path.as_str(), Box::new(mtime_dt) as Box<dyn ToSql>)
+            })
+            .collect();
+        self.update_column(vault, "mtime", "TIMESTAMPTZ", &values)
+    }
+
+    /// The links of every file in `vault` by path.
     pub fn get_all_links(
         &self,
+        vault: &str,
     ) -> Result<std::collections::HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT path, to_json(links) FROM documents")?;
-        let mut rows = stmt.query([])?;
+            .prepare("SELECT path, to_json(links) FROM documents WHERE vault = ?")?;
+        let mut rows = stmt.query(params![vault])?;
 
         let mut link_map = std::collections::HashMap::new();
         while let Some(row) = rows.next()? {
@@ -128,12 +665,338 @@ impl Database {
         Ok(link_map)
     }
 
+    /// The names each file in `vault` is linked by: a note's file name and
+    /// aliases, and a canvas's also with its extension; an attachment's path
+    /// and its trailing folders, like `assets/a.png` and `a.png`, but the
+    /// bare file name only when no other attachment shares it.
+    pub fn get_link_keys(
+        &self,
+        vault: &str,
+    ) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT d.path, d.name, d.ext, to_json(d.aliases), a.path IS NOT NULL
+             FROM documents d LEFT JOIN attachments a USING (vault, path)
+             WHERE d.vault = ?",
+        )?;
+        let mut rows = stmt.query(params![vault])?;
+
+        let mut files: Vec<(String, String, String, String, bool)> = Vec::new();
+        while let Some(row) = rows.next()? {
+            files.push((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ));
+        }
+
+        let mut file_names: HashMap<String, usize> = HashMap::new();
+        for (_, name, ext, _, _) in files.iter().filter(|file| file.4) {
+            *file_names.entry(format!("{}.{}", name, ext)).or_default() += 1;
+        }
+
+        let mut keys = HashMap::new();
+        for (path, name, ext, aliases_json, is_attachment) in files {
+            let file_keys = if is_attachment {
+                let file_name = format!("{}.{}", name, ext);
+                let mut file_keys: Vec<String> = std::iter::once(path.as_str())
+                    .chain(path.match_indices('/').map(|(i, _)| &path[i + 1..]))
+                    .filter(|key| key.contains('/'))
+                    .map(str::to_string)
+                    .collect();
+                if file_names[&file_name] == 1 {
+                    file_keys.push(file_name);
+                }
+                file_keys
+            } else {
+                let aliases: Vec<String> = serde_json::from_str(&aliases_json).unwrap_or_default();
+                let file_name = (ext != "md").then(|| format!("{}.{}", name, ext));
+                std::iter::once(name)
+                    .chain(aliases)
+                    .chain(file_name)
+                    .collect()
+            };
+            keys.insert(path, file_keys);
+        }
+
+        Ok(keys)
+    }
+
+    /// The paths of the files in `vault` by each name they are linked by,
+    /// from `get_link_keys`.
+    fn get_linked_paths(
+        &self,
+        vault: &str,
+    ) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+        let mut linked: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, keys) in self.get_link_keys(vault)? {
+            for key in keys {
+                linked.entry(key).or_default().push(path.clone());
+            }
+        }
+        Ok(linked)
+    }
+
+    /// `vault` alone, or else every vault with files indexed.
+    fn vault_names(&self, vault: Option<&str>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        if let Some(vault) = vault {
+            return Ok(vec![vault.to_string()]);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT vault FROM documents ORDER BY vault")?;
+        let mut rows = stmt.query([])?;
+
+        let mut vaults = Vec::new();
+        while let Some(row) = rows.next()? {
+            vaults.push(row.get(0)?);
+        }
+
+        Ok(vaults)
+    }
+
+    /// The tags of every file, or of those in `vault`, keyed by
+    /// `vault/path` since paths repeat across vaults.
+    pub fn get_all_tags(
+        &self,
+        vault: Option<&str>,
+    ) -> Result<std::collections::HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT vault || '/' || path, to_json(tags) FROM documents
+             WHERE ?1 IS NULL OR vault = ?1",
+        )?;
+        let mut rows = stmt.query(params![vault])?;
+
+        let mut tag_map = std::collections::HashMap::new();
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let tags_json: String = row.get(1)?;
+            let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+            tag_map.insert(path, tags);
+        }
+
+        Ok(tag_map)
+    }
+
+    /// Returns the extraction errors of every document in `vault` that has
+    /// any.
+    pub fn get_document_errors(
+        &self,
+        vault: &str,
+    ) -> Result<BTreeMap<String, Vec<ExtractError>>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, errors FROM documents
+             WHERE vault = ? AND json_array_length(errors) > 0",
+        )?;
+        let mut rows = stmt.query(params![vault])?;
+
+        let mut documents = BTreeMap::new();
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let errors_json: String = row.get(1)?;
+            let errors: Vec<ExtractError> = serde_json::from_str(&errors_json).unwrap_or_default();
+            documents.insert(path, errors);
+        }
+
+        Ok(documents)
+    }
+
+    /// Returns callouts with the vault and path of their note, optionally
+    /// filtered by type, fold state and vault, ordered by vault, path and
+    /// position.
+    pub fn get_callouts(
+        &self,
+        kind: Option<&str>,
+        fold: Option<&str>,
+        vault: Option<&str>,
+    ) -> Result<FileItems<Callout>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT vault, path, callout_type, title, fold, body, depth, parent, start_line, end_line
+             FROM callouts
+             WHERE (?1 IS NULL OR callout_type = lower(?1)) AND (?2 IS NULL OR fold = ?2)
+               AND (?3 IS NULL OR vault = ?3)
+             ORDER BY vault, path, idx",
+        )?;
+        let mut rows = stmt.query(params![kind, fold, vault])?;
+
+        let mut callouts = Vec::new();
+        while let Some(row) = rows.next()? {
+            let depth: i64 = row.get(6)?;
+            let parent: Option<i64> = row.get(7)?;
+            let start_line: i64 = row.get(8)?;
+            let end_line: i64 = row.get(9)?;
+            let callout = Callout {
+                kind: row.get(2)?,
+                title: row.get(3)?,
+                fold: row.get(4)?,
+                body: row.get(5)?,
+                depth: depth as usize,
+                parent: parent.map(|p| p as usize),
+                start_line: start_line as usize,
+                end_line: end_line as usize,
+            };
+            callouts.push((row.get(0)?, row.get(1)?, callout));
+        }
+
+        Ok(callouts)
+    }
+
+    /// Returns every block, or those in `vault`, with the vault and path of
+    /// its note, ordered by vault, path and position.
+    pub fn get_blocks(
+        &self,
+        vault: Option<&str>,
+    ) -> Result<FileItems<Block>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT vault, path, block_id, start_line, end_line, text FROM blocks
+             WHERE ?1 IS NULL OR vault = ?1
+             ORDER BY vault, path, start_line",
+        )?;
+        let mut rows = stmt.query(params![vault])?;
+
+        let mut blocks = Vec::new();
+        while let Some(row) = rows.next()? {
+            let start_line: i64 = row.get(3)?;
+            let end_line: i64 = row.get(4)?;
+            let block = Block {
+                id: row.get(2)?,
+                text: row.get(5)?,
+                start_line: start_line as usize,
+                end_line: end_line as usize,
+            };
+            blocks.push((row.get(0)?, row.get(1)?, block));
+        }
+
+        Ok(blocks)
+    }
+
+    /// Returns `(vault, path, link)` for every `[[note#^block-id]]` link,
+    /// or those in `vault`, whose target file in the same vault, resolved
+    /// like any other link, has no block with that ID. A link without a note
+    /// name (`[[#^id]]`) points into the linking note itself.
+    pub fn get_broken_block_refs(
+        &self,
+        vault: Option<&str>,
+    ) -> Result<FileItems<String>, Box<dyn std::error::Error>> {
+        let mut broken = Vec::new();
+        for vault in self.vault_names(vault)? {
+            let linked = self.get_linked_paths(&vault)?;
+            let mut stmt = self
+                .conn
+                .prepare("SELECT path, block_id FROM blocks WHERE vault = ?")?;
+            let mut rows = stmt.query(params![vault])?;
+            let mut blocks: HashSet<(String, String)> = HashSet::new();
+            while let Some(row) = rows.next()? {
+                blocks.insert((row.get(0)?, row.get(1)?));
+            }
+
+            for (path, links) in self.get_all_links(&vault)? {
+                for link in links {
+                    let Some((_, fragment)) = link.split_once("#^") else {
+                        continue;
+                    };
+                    let block_id: String = fragment
+                        .chars()
+                        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+                        .collect();
+                    let targets: Vec<&String> = if link_target(&link).is_empty() {
+                        vec![&path]
+                    } else {
+                        link_names_of(&link)
+                            .flat_map(|name| linked.get(name).into_iter().flatten())
+                            .collect()
+                    };
+                    if !targets
+                        .into_iter()
+                        .any(|target| blocks.contains(&(target.clone(), block_id.clone())))
+                    {
+                        broken.push((vault.clone(), path.clone(), link));
+                    }
+                }
+            }
+        }
+        broken.sort();
+
+        Ok(broken)
+    }
+
+    /// Returns `(vault, path, embed)` for every `![[embed]]`, or those in
+    /// `vault`, that resolves to no file indexed in the same vault. Embeds
+    /// resolve like links, so one is broken exactly when it gives no file a
+    /// backlink.
+    pub fn get_broken_embeds(
+        &self,
+        vault: Option<&str>,
+    ) -> Result<FileItems<String>, Box<dyn std::error::Error>> {
+        let mut broken = Vec::new();
+        for vault in self.vault_names(vault)? {
+            let linked = self.get_linked_paths(&vault)?;
+            let mut stmt = self
+                .conn
+                .prepare("SELECT path, unnest(embeds) FROM documents WHERE vault = ?")?;
+            let mut rows = stmt.query(params![vault])?;
+            while let Some(row) = rows.next()? {
+                let embed: String = row.get(1)?;
+                if link_target(&embed).is_empty()
+                    || link_names_of(&embed).any(|name| linked.contains_key(name))
+                {
+                    continue;
+                }
+                broken.push((vault.clone(), row.get(0)?, embed));
+            }
+        }
+        broken.sort();
+
+        Ok(broken)
+    }
+
+    /// Returns attachments, or those in `vault`, with their vault, path and
+    /// metadata, ordered by vault and path. With `unused_only`, only those
+    /// that no note links to or embeds.
+    pub fn get_attachments(
+        &self,
+        unused_only: bool,
+        vault: Option<&str>,
+    ) -> Result<FileItems<Attachment>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT vault, path, a.mime, a.width, a.height
+             FROM attachments a JOIN documents d USING (vault, path)
+             WHERE (NOT ?1 OR len(d.backlinks) = 0) AND (?2 IS NULL OR vault = ?2)
+             ORDER BY vault, path",
+        )?;
+        let mut rows = stmt.query(params![unused_only, vault])?;
+
+        let mut attachments = Vec::new();
+        while let Some(row) = rows.next()? {
+            let width: Option<i64> = row.get(3)?;
+            let height: Option<i64> = row.get(4)?;
+            let attachment = Attachment {
+                mime: row.get(2)?,
+                width: width.map(|w| w as u64),
+                height: height.map(|h| h as u64),
+            };
+            attachments.push((row.get(0)?, row.get(1)?, attachment));
+        }
+
+        Ok(attachments)
+    }
+
     pub fn query(
         &self,
         sql: &str,
         _fields: &str,
         limit: usize,
     ) -> Result<Vec<Vec<String>>, Box<dyn std::error::Error>> {
+        Ok(self.query_table(sql, limit)?.rows)
+    }
+
+    /// Runs `sql` like `query`, keeping the names of the columns.
+    pub fn query_table(
+        &self,
+        sql: &str,
+        limit: usize,
+    ) -> Result<QueryTable, Box<dyn std::error::Error>> {
         let sql = format!("{} LIMIT {}", sql, limit);
 
         let mut results = Vec::new();
@@ -171,6 +1034,11 @@ impl Database {
                             ts.to_string()
                         }
                     }
+                    duckdb::types::Value::Date32(days) => {
+                        chrono::DateTime::from_timestamp(days as i64 * 86_400, 0)
+                            .map(|dt| dt.format("%Y-%m-%d").to_string())
+                            .unwrap_or_else(|| days.to_string())
+                    }
                     duckdb::types::Value::List(list) => {
                         let items: Vec<String> = list
                             .iter()
@@ -188,8 +1056,295 @@ impl Database {
             results.push(result_row);
         }
 
-        Ok(results)
+        Ok(QueryTable {
+            columns: column_names,
+            rows: results,
+        })
+    }
+}
+
+/// Whether `err` is DuckDB refusing a row whose key is already stored, as
+/// `Database::insert_documents` does for a path that is indexed.
+pub fn is_conflict(err: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        err.downcast_ref::<duckdb::Error>(),
+        Some(duckdb::Error::DuckDBFailure(_, Some(message))) if message.contains("violates primary key constraint")
+    )
+}
+
+/// Returns the note name a wikilink points at, dropping any `#heading`
+/// fragment and `|display` alias.
+pub fn link_target(link: &str) -> &str {
+    link.split(['|', '#']).next().unwrap_or(link).trim()
+}
+
+/// The names a link resolves by: its target and, for targets in a folder
+/// like `assets/mockup.png`, the file name, as embeds often carry it. A
+/// file is linked when one of them is among its `get_link_keys`.
+pub fn link_names_of(link: &str) -> impl Iterator<Item = &str> {
+    let target = link_target(link);
+    std::iter::once(target).chain(target.rsplit_once('/').map(|(_, name)| name))
+}
+
+/// Rows returned by a query, with every value rendered as text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// A row to write for a document: its table, the columns given and a value
+/// for each.
+struct Row<'a> {
+    table: &'static str,
+    columns: Vec<&'a str>,
+    values: Vec<Box<dyn ToSql + 'a>>,
+}
+
+/// Columns of `documents` every file has a value for; custom extractor
+/// columns follow.
+const DOCUMENT_COLUMNS: &[&str] = &[
+    "path",
+    "folder",
+    "name",
+    "ext",
+    "size",
+    "ctime",
+    "mtime",
+    "content",
+    "tags",
+    "tag_sources",
+    "aliases",
+    "links",
+    "backlinks",
+    "embeds",
+    "properties",
+    "property_sources",
+    "words",
+    "chars",
+    "lines",
+    "reading_time",
+    "title",
+    "excerpt",
+    "frontmatter_format",
+    "errors",
+    "callout_types",
+    "code_languages",
+    "highlights",
+    "footnotes",
+    "comments",
+    "date",
+    "date_source",
+    "body",
+    "plain_text",
+    "hash",
+];
+
+/// Lists and maps are written as JSON, which DuckDB casts to the column type.
+fn json<T: Serialize + ?Sized>(value: &T) -> Result<Box<dyn ToSql>, serde_json::Error> {
+    Ok(Box::new(serde_json::to_string(value)?))
+}
+
+/// Every row a document is stored as, across `DOCUMENT_TABLES`.
+fn document_rows(doc: &Document) -> Result<Vec<Row<'_>>, Box<dyn std::error::Error>> {
+    let ctime = chrono::DateTime::from_timestamp(doc.ctime, 0).unwrap();
+    let mtime = chrono::DateTime::from_timestamp(doc.mtime, 0).unwrap();
+    let callout_types = unique(doc.callouts.iter().map(|c| c.kind.as_str()));
+    let code_languages = unique(doc.code_blocks.iter().filter_map(|c| c.language.as_deref()));
+
+    let mut columns = DOCUMENT_COLUMNS.to_vec();
+    let mut values: Vec<Box<dyn ToSql>> = vec![
+        Box::new(&doc.path),
+        Box::new(&doc.folder),
+        Box::new(&doc.name),
+        Box::new(&doc.ext),
+        Box::new(doc.size as i64),
+        Box::new(ctime),
+        Box::new(mtime),
+        Box::new(&doc.content),
+        json(&doc.tags)?,
+        json(&doc.tag_sources)?,
+        json(&doc.aliases)?,
+        json(&doc.links)?,
+        json(&doc.backlinks)?,
+        json(&doc.embeds)?,
+        json(&doc.properties)?,
+        json(&doc.property_sources)?,
+        Box::new(doc.words as i64),
+        Box::new(doc.chars as i64),
+        Box::new(doc.lines as i64),
+        Box::new(doc.reading_time as i64),
+        Box::new(&doc.title),
+        Box::new(&doc.excerpt),
+        Box::new(doc.frontmatter_format.map(|f| f.as_str())),
+        json(&doc.errors)?,
+        json(&callout_types)?,
+        json(&code_languages)?,
+        json(&doc.highlights)?,
+        json(&doc.footnotes)?,
+        json(&doc.comments)?,
+        Box::new(doc.date),
+        Box::new(doc.date_source.as_str()),
+        Box::new(&doc.body),
+        Box::new(&doc.plain_text),
+        Box::new(&doc.hash),
+    ];
+    for (name, list) in &doc.custom_fields {
+        columns.push(name);
+        values.push(json(list)?);
+    }
+    let mut rows = vec![Row {
+        table: "documents",
+        columns,
+        values,
+    }];
+
+    for block in &doc.blocks {
+        rows.push(Row {
+            table: "blocks",
+            columns: vec!["path", "block_id", "text", "start_line", "end_line"],
+            values: vec![
+                Box::new(&doc.path),
+                Box::new(&block.id),
+                Box::new(&block.text),
+                Box::new(block.start_line as i64),
+                Box::new(block.end_line as i64),
+            ],
+        });
+    }
+
+    for (idx, callout) in doc.callouts.iter().enumerate() {
+        rows.push(Row {
+            table: "callouts",
+            columns: vec![
+                "path",
+                "idx",
+                "callout_type",
+                "title",
+                "fold",
+                "body",
+                "depth",
+                "parent",
+                "start_line",
+                "end_line",
+            ],
+            values: vec![
+                Box::new(&doc.path),
+                Box::new(idx as i64),
+                Box::new(&callout.kind),
+                Box::new(&callout.title),
+                Box::new(&callout.fold),
+                Box::new(&callout.body),
+                Box::new(callout.depth as i64),
+                Box::new(callout.parent.map(|p| p as i64)),
+                Box::new(callout.start_line as i64),
+                Box::new(callout.end_line as i64),
+            ],
+        });
+    }
+
+    for (idx, code_block) in doc.code_blocks.iter().enumerate() {
+        rows.push(Row {
+            table: "code_blocks",
+            columns: vec![
+                "path",
+                "idx",
+                "language",
+                "info",
+                "text",
+                "start_line",
+                "end_line",
+            ],
+            values: vec![
+                Box::new(&doc.path),
+                Box::new(idx as i64),
+                Box::new(&code_block.language),
+                Box::new(&code_block.info),
+                Box::new(&code_block.text),
+                Box::new(code_block.start_line as i64),
+                Box::new(code_block.end_line as i64),
+            ],
+        });
+    }
+
+    for node in &doc.canvas_nodes {
+        rows.push(Row {
+            table: "canvas_nodes",
+            columns: vec![
+                "path",
+                "node_id",
+                "node_type",
+                "text",
+                "file",
+                "subpath",
+                "url",
+                "label",
+                "x",
+                "y",
+                "width",
+                "height",
+            ],
+            values: vec![
+                Box::new(&doc.path),
+                Box::new(&node.id),
+                Box::new(&node.kind),
+                Box::new(&node.text),
+                Box::new(&node.file),
+                Box::new(&node.subpath),
+                Box::new(&node.url),
+                Box::new(&node.label),
+                Box::new(node.x),
+                Box::new(node.y),
+                Box::new(node.width),
+                Box::new(node.height),
+            ],
+        });
+    }
+
+    for edge in &doc.canvas_edges {
+        rows.push(Row {
+            table: "canvas_edges",
+            columns: vec!["path", "edge_id", "from_node", "to_node", "label"],
+            values: vec![
+                Box::new(&doc.path),
+                Box::new(&edge.id),
+                Box::new(&edge.from_node),
+                Box::new(&edge.to_node),
+                Box::new(&edge.label),
+            ],
+        });
+    }
+
+    if let Some(attachment) = &doc.attachment {
+        rows.push(Row {
+            table: "attachments",
+            columns: vec!["path", "mime", "width", "height"],
+            values: vec![
+                Box::new(&doc.path),
+                Box::new(&attachment.mime),
+                Box::new(attachment.width.map(|w| w as i64)),
+                Box::new(attachment.height.map(|h| h as i64)),
+            ],
+        });
+    }
+
+    // Every row is keyed by the vault as well as the path.
+    for row in &mut rows {
+        row.columns.insert(0, "vault");
+        row.values.insert(0, Box::new(&doc.vault));
     }
+    Ok(rows)
+}
+
+/// Distinct values in first-seen order.
+fn unique<'a>(values: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut seen: Vec<&str> = Vec::new();
+    for value in values {
+        if !seen.contains(&value) {
+            seen.push(value);
+        }
+    }
+    seen
 }
 
 #[cfg(test)]
@@ -205,25 +1360,56 @@ mod tests {
 
     fn create_test_document(name: &str) -> Document {
         Document {
-            path: format!("/test/{}.md", name),
-            folder: "/test".to_string(),
+            vault: "notes".to_string(),
+            path: format!("test/{}.md", name),
+            folder: "test".to_string(),
             name: name.to_string(),
             ext: "md".to_string(),
             size: 1000,
             ctime: 1704067200,
             mtime: 1704067200,
-            content: format!("Content of {}", name),
+            hash: format!("hash-{}", name),
+            date: chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            date_source: DateSource::Filesystem,
+            content: Some(format!("Content of {}", name)),
+            body: format!("Content of {}", name),
+            plain_text: format!("Content of {}", name),
             tags: vec!["test".to_string(), "example".to_string()],
+            tag_sources: BTreeMap::new(),
+            aliases: vec![],
             links: vec!["link1".to_string()],
             backlinks: vec![],
             embeds: vec!["embed1.png".to_string()],
+            highlights: vec![],
+            footnotes: vec![],
+            comments: vec![],
+            custom_fields: BTreeMap::new(),
             properties: serde_json::json!({
                 "title": name,
                 "category": "test"
             }),
+            frontmatter_format: Some(FrontmatterFormat::Yaml),
+            errors: vec![],
+            property_sources: BTreeMap::new(),
+            blocks: vec![],
+            callouts: vec![],
+            code_blocks: vec![],
+            canvas_nodes: vec![],
+            canvas_edges: vec![],
+            attachment: None,
+            words: 3,
+            chars: 15,
+            lines: 1,
+            reading_time: 1,
+            title: Some(name.to_string()),
+            excerpt: format!("Content of {}", name),
         }
     }
 
+    fn upsert(db: &Database, doc: &Document) {
+        db.replace_documents(std::slice::from_ref(doc)).unwrap();
+    }
+
     fn cleanup_db(db_path: &std::path::Path) {
         let _ = std::fs::remove_file(db_path);
         let _ = std::fs::remove_file(db_path.with_extension("duckdb.wal"));
@@ -242,6 +1428,77 @@ mod tests {
         cleanup_db(&db_path);
     }
 
+    #[test]
+    fn test_rebuild_older_schema() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        // As the first releases left it: keyed by path alone, no versions.
+        {
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE documents (
+                    path TEXT PRIMARY KEY,
+                    folder TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    ext TEXT NOT NULL,
+                    size BIGINT NOT NULL,
+                    ctime TIMESTAMPTZ NOT NULL,
+                    mtime TIMESTAMPTZ NOT NULL,
+                    content TEXT,
+                    tags VARCHAR[],
+                    links VARCHAR[],
+                    backlinks VARCHAR[],
+                    embeds VARCHAR[],
+                    properties JSON
+                );
+                CREATE INDEX idx_mtime ON documents(mtime);
+                INSERT INTO documents VALUES ('old.md', '', 'old', 'md', 3,
+                    now(), now(), 'old', [], [], [], [], '{}');",
+            )
+            .unwrap();
+        }
+
+        let db = Database::new(&db_path).unwrap();
+        assert!(db.rebuilt());
+        assert_eq!(db.get_mtime("notes", "old.md").unwrap(), None);
+        upsert(&db, &create_test_document("test1"));
+        assert!(db.get_mtime("notes", "test/test1.md").unwrap().is_some());
+        drop(db);
+
+        let db = Database::new(&db_path).unwrap();
+        assert!(!db.rebuilt());
+        assert!(db.get_mtime("notes", "test/test1.md").unwrap().is_some());
+
+        // One from an older version is rebuilt as well.
+        db.conn
+            .execute(
+                "UPDATE metadata SET value = '16' WHERE key = 'schema_version'",
+                [],
+            )
+            .unwrap();
+        drop(db);
+        let db = Database::new(&db_path).unwrap();
+        assert!(db.rebuilt());
+        assert_eq!(db.get_mtime("notes", "test/test1.md").unwrap(), None);
+
+        // A database from a newer version is left alone.
+        db.conn
+            .execute(
+                "UPDATE metadata SET value = '99' WHERE key = 'schema_version'",
+                [],
+            )
+            .unwrap();
+        drop(db);
+        let err = Database::new(&db_path).err().unwrap();
+        assert!(err.to_string().contains("schema version 99"));
+
+        cleanup_db(&db_path);
+    }
+
     #[test]
     fn test_upsert_and_get_mtime() {
         let temp_dir = std::env::temp_dir();
@@ -253,12 +1510,39 @@ mod tests {
         let db = Database::new(&db_path).unwrap();
 
         let doc = create_test_document("test1");
-        db.upsert_document(&doc).unwrap();
+        upsert(&db, &doc);
 
-        let mtime = db.get_mtime(&doc.path).unwrap();
+        let mtime = db.get_mtime("notes", &doc.path).unwrap();
         assert!(mtime.is_some());
         assert_eq!(mtime.unwrap(), doc.mtime);
 
+        db.update_mtimes("notes", &[(doc.path.clone(), doc.mtime + 60)])
+            .unwrap();
+        assert_eq!(
+            db.get_mtime("notes", &doc.path).unwrap(),
+            Some(doc.mtime + 60)
+        );
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_large_file_size() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let mut doc = create_test_document("recording");
+        doc.size = 5 << 30;
+        upsert(&db, &doc);
+
+        let states = db.get_file_states("notes").unwrap();
+        assert_eq!(states[&doc.path].size, 5 << 30);
+
         cleanup_db(&db_path);
     }
 
@@ -272,7 +1556,7 @@ mod tests {
         ));
         let db = Database::new(&db_path).unwrap();
 
-        let mtime = db.get_mtime("/nonexistent/path.md").unwrap();
+        let mtime = db.get_mtime("notes", "/nonexistent/path.md").unwrap();
         assert!(mtime.is_none());
 
         cleanup_db(&db_path);
@@ -290,14 +1574,14 @@ mod tests {
         let db = Database::new(&db_path).unwrap();
 
         let mut doc = create_test_document("test1");
-        db.upsert_document(&doc).unwrap();
+        upsert(&db, &doc);
 
         // Update document
         doc.size = 2000;
         doc.mtime = 1704153600;
-        db.upsert_document(&doc).unwrap();
+        upsert(&db, &doc);
 
-        let mtime = db.get_mtime(&doc.path).unwrap();
+        let mtime = db.get_mtime("notes", &doc.path).unwrap();
         let actual_mtime = mtime.unwrap();
         assert_eq!(
             actual_mtime, 1704153600,
@@ -322,10 +1606,10 @@ mod tests {
         let mut doc2 = create_test_document("doc2");
         doc2.links = vec!["doc1".to_string()];
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
-        let link_map = db.get_all_links().unwrap();
+        let link_map = db.get_all_links("notes").unwrap();
         assert_eq!(link_map.len(), 2);
         assert!(link_map.contains_key(&doc1.path));
         assert!(link_map.contains_key(&doc2.path));
@@ -334,6 +1618,169 @@ mod tests {
         cleanup_db(&db_path);
     }
 
+    #[test]
+    fn test_blocks_and_broken_block_refs() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let mut doc1 = create_test_document("doc1");
+        doc1.aliases = vec!["First".to_string()];
+        doc1.links = vec!["#^own".to_string()];
+        doc1.blocks = vec![Block {
+            id: "abc123".to_string(),
+            text: "A quoted paragraph".to_string(),
+            start_line: 3,
+            end_line: 4,
+        }];
+
+        let mut doc2 = create_test_document("doc2");
+        doc2.links = vec![
+            "doc1#^abc123".to_string(),
+            "First#^abc123|see this".to_string(),
+            "doc1#^missing".to_string(),
+            "nowhere#^abc123".to_string(),
+            "test/doc1#^abc123".to_string(),
+            "elsewhere/doc1#^missing".to_string(),
+        ];
+
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
+
+        let blocks = db.get_blocks(None).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1, doc1.path);
+        assert_eq!(blocks[0].2, doc1.blocks[0]);
+
+        let broken = db.get_broken_block_refs(None).unwrap();
+        let broken_ref =
+            |doc: &Document, link: &str| ("notes".to_string(), doc.path.clone(), link.to_string());
+        assert_eq!(
+            broken,
+            vec![
+                broken_ref(&doc1, "#^own"),
+                broken_ref(&doc2, "doc1#^missing"),
+                broken_ref(&doc2, "elsewhere/doc1#^missing"),
+                broken_ref(&doc2, "nowhere#^abc123"),
+            ]
+        );
+
+        // Re-indexing a document replaces its blocks.
+        doc1.blocks[0].id = "own".to_string();
+        upsert(&db, &doc1);
+        let broken = db.get_broken_block_refs(None).unwrap();
+        assert_eq!(broken.len(), doc2.links.len());
+        assert!(broken.iter().all(|(_, path, _)| path == &doc2.path));
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_callouts_filtering() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let callout = |kind: &str, fold: Option<&str>, line: usize| Callout {
+            kind: kind.to_string(),
+            title: Some(format!("{} title", kind)),
+            fold: fold.map(|f| f.to_string()),
+            body: "body".to_string(),
+            depth: 1,
+            parent: None,
+            start_line: line,
+            end_line: line + 1,
+        };
+
+        let mut doc1 = create_test_document("doc1");
+        doc1.callouts = vec![
+            callout("question", Some("open"), 3),
+            callout("question", Some("closed"), 8),
+            callout("warning", None, 12),
+        ];
+        upsert(&db, &doc1);
+
+        assert_eq!(db.get_callouts(None, None, None).unwrap().len(), 3);
+
+        let open_questions = db
+            .get_callouts(Some("Question"), Some("open"), None)
+            .unwrap();
+        assert_eq!(open_questions.len(), 1);
+        assert_eq!(open_questions[0].1, doc1.path);
+        assert_eq!(open_questions[0].2, doc1.callouts[0]);
+
+        let results = db
+            .query(
+                "SELECT name FROM documents WHERE 'warning' = ANY(callout_types)",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results, vec![vec!["doc1".to_string()]]);
+
+        doc1.callouts.clear();
+        upsert(&db, &doc1);
+        assert!(db.get_callouts(None, None, None).unwrap().is_empty());
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_code_blocks_query() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let mut doc1 = create_test_document("doc1");
+        doc1.code_blocks = vec![
+            CodeBlock::new("sql".to_string(), "SELECT 1;".to_string(), 3, 5),
+            CodeBlock::new("rust".to_string(), "fn main() {}".to_string(), 7, 9),
+            CodeBlock::new(String::new(), "plain".to_string(), 11, 13),
+        ];
+        upsert(&db, &doc1);
+        upsert(&db, &create_test_document("doc2"));
+
+        let fields = "name, code.start_line, code.text";
+        let sql = crate::query::build_sql_from(
+            crate::query::RowSource::CodeBlocks,
+            "code.language == 'sql'",
+            fields,
+        )
+        .unwrap();
+        let results = db.query(&sql, fields, 10).unwrap();
+        assert_eq!(
+            results,
+            vec![vec![
+                "doc1".to_string(),
+                "3".to_string(),
+                "SELECT 1;".to_string()
+            ]]
+        );
+
+        let results = db
+            .query(
+                "SELECT name FROM documents WHERE 'rust' = ANY(code_languages) ORDER BY name",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results, vec![vec!["doc1".to_string()]]);
+
+        cleanup_db(&db_path);
+    }
+
     #[test]
     fn test_query_documents() {
         let temp_dir = std::env::temp_dir();
@@ -348,8 +1795,8 @@ mod tests {
         let mut doc2 = create_test_document("doc2");
         doc2.name = "other".to_string();
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
         let results = db.query("SELECT * FROM documents", "*", 10).unwrap();
         assert_eq!(results.len(), 2);
@@ -370,8 +1817,8 @@ mod tests {
         let doc1 = create_test_document("special");
         let doc2 = create_test_document("other");
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
         let results = db
             .query("SELECT * FROM documents WHERE name = 'special'", "*", 10)
@@ -393,7 +1840,7 @@ mod tests {
 
         for i in 0..10 {
             let doc = create_test_document(&format!("doc{}", i));
-            db.upsert_document(&doc).unwrap();
+            upsert(&db, &doc);
         }
 
         let results = db.query("SELECT * FROM documents", "*", 5).unwrap();
@@ -418,8 +1865,8 @@ mod tests {
         let mut doc2 = create_test_document("doc2");
         doc2.tags = vec!["todo".to_string()];
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
         let results = db
             .query(
@@ -429,7 +1876,49 @@ mod tests {
             )
             .unwrap();
         assert_eq!(results.len(), 1);
+        assert!(results[0][1].contains("doc1"));
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_query_has_tag_matches_descendants() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let mut doc1 = create_test_document("doc1");
+        doc1.tags = vec!["projects/mobile".to_string()];
+
+        let mut doc2 = create_test_document("doc2");
+        doc2.tags = vec!["projects".to_string()];
+
+        let mut doc3 = create_test_document("doc3");
+        doc3.tags = vec!["projectsx".to_string()];
+
+        let mut doc4 = create_test_document("doc4");
+        doc4.tags = vec!["Projects/Web".to_string()];
+
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
+        upsert(&db, &doc3);
+        upsert(&db, &doc4);
+
+        let sql = crate::query::build_sql("has_tag(tags, 'projects')", "path").unwrap();
+        let results = db
+            .query(&format!("{} ORDER BY path", sql), "*", 10)
+            .unwrap();
+        assert_eq!(results.len(), 3);
         assert!(results[0][0].contains("doc1"));
+        assert!(results[1][0].contains("doc2"));
+        assert!(results[2][0].contains("doc4"));
+
+        let tag_map = db.get_all_tags(None).unwrap();
+        assert_eq!(tag_map["notes/test/doc1.md"], vec!["projects/mobile"]);
 
         cleanup_db(&db_path);
     }
@@ -450,8 +1939,8 @@ mod tests {
         let mut doc2 = create_test_document("doc2");
         doc2.links = vec!["other".to_string()];
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
         let results = db
             .query(
@@ -461,7 +1950,7 @@ mod tests {
             )
             .unwrap();
         assert_eq!(results.len(), 1);
-        assert!(results[0][0].contains("doc1"));
+        assert!(results[0][1].contains("doc1"));
 
         cleanup_db(&db_path);
     }
@@ -482,8 +1971,8 @@ mod tests {
         let mut doc2 = create_test_document("doc2");
         doc2.embeds = vec!["other.png".to_string()];
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
         let results = db
             .query(
@@ -493,7 +1982,7 @@ mod tests {
             )
             .unwrap();
         assert_eq!(results.len(), 1);
-        assert!(results[0][0].contains("doc1"));
+        assert!(results[0][1].contains("doc1"));
 
         cleanup_db(&db_path);
     }
@@ -514,14 +2003,214 @@ mod tests {
         let mut doc2 = create_test_document("doc2");
         doc2.tags = vec!["tag1".to_string()];
 
-        db.upsert_document(&doc1).unwrap();
-        db.upsert_document(&doc2).unwrap();
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
 
         let results = db
             .query("SELECT * FROM documents WHERE 'tag1' = ANY(tags)", "*", 10)
             .unwrap();
         assert_eq!(results.len(), 1);
-        assert!(results[0][0].contains("doc2"));
+        assert!(results[0][1].contains("doc2"));
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_delete_document() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let doc1 = create_test_document("doc1");
+        let mut doc2 = create_test_document("doc2");
+        doc2.backlinks = vec![doc1.path.clone()];
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
+
+        assert_eq!(
+            db.get_paths_under("notes", "test").unwrap(),
+            vec!["test/doc1.md".to_string(), "test/doc2.md".to_string()]
+        );
+        assert!(db.get_paths_under("notes", "tes").unwrap().is_empty());
+
+        db.delete_document("notes", &doc1.path).unwrap();
+        assert_eq!(db.get_mtime("notes", &doc1.path).unwrap(), None);
+        let results = db
+            .query(
+                "SELECT backlinks FROM documents WHERE name = 'doc2'",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results, vec![vec!["[]".to_string()]]);
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_rename_document() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let mut doc1 = create_test_document("doc1");
+        doc1.blocks = vec![Block {
+            id: "abc".to_string(),
+            text: "A block".to_string(),
+            start_line: 1,
+            end_line: 1,
+        }];
+        let mut doc2 = create_test_document("doc2");
+        doc2.backlinks = vec![doc1.path.clone()];
+        upsert(&db, &doc1);
+        upsert(&db, &doc2);
+
+        db.rename_document("notes", &doc1.path, "test/moved/doc1.md")
+            .unwrap();
+        let states = db.get_file_states("notes").unwrap();
+        assert!(!states.contains_key(&doc1.path));
+        let state = &states["test/moved/doc1.md"];
+        assert_eq!(state.hash, "hash-doc1");
+        assert_eq!(state.mtime, 1704067200);
+        let results = db
+            .query(
+                "SELECT backlinks FROM documents WHERE name = 'doc2'",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results, vec![vec![r#"["test/moved/doc1.md"]"#.to_string()]]);
+
+        // A rename that fails partway, here onto a path already taken,
+        // leaves every table as it was.
+        assert!(
+            db.rename_document("notes", "test/moved/doc1.md", &doc2.path)
+                .is_err()
+        );
+        let blocks = db.get_blocks(None).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1, "test/moved/doc1.md");
+        let results = db
+            .query(
+                "SELECT backlinks FROM documents WHERE name = 'doc2'",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results, vec![vec![r#"["test/moved/doc1.md"]"#.to_string()]]);
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_insert_documents_bulk() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+        db.add_list_columns(&["tickets"]).unwrap();
+
+        let mut doc1 = create_test_document("doc1");
+        doc1.code_blocks = vec![CodeBlock::new(
+            "sql".to_string(),
+            "SELECT 1;".to_string(),
+            3,
+            5,
+        )];
+        doc1.custom_fields
+            .insert("tickets".to_string(), vec!["OPS-7".to_string()]);
+        let doc2 = create_test_document("doc2");
+        db.insert_documents(&[doc1.clone(), doc2]).unwrap();
+
+        assert_eq!(db.get_mtime("notes", &doc1.path).unwrap(), Some(1704067200));
+        let results = db
+            .query(
+                "SELECT name, tags, code_languages, tickets, date FROM documents ORDER BY name",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results[0][0], "doc1");
+        assert_eq!(results[0][1], r#"["test","example"]"#);
+        assert_eq!(results[0][2], r#"["sql"]"#);
+        assert_eq!(results[0][3], r#"["OPS-7"]"#);
+        assert_eq!(results[0][4], "2024-01-01");
+        assert_eq!(results[1][3], "");
+        let results = db
+            .query("SELECT path, text FROM code_blocks", "*", 10)
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![vec![doc1.path.clone(), "SELECT 1;".to_string()]]
+        );
+
+        // Replacing keeps one row per path and drops stale child rows.
+        doc1.code_blocks.clear();
+        db.replace_documents(std::slice::from_ref(&doc1)).unwrap();
+        let results = db.query("SELECT count(*) FROM documents", "*", 10).unwrap();
+        assert_eq!(results, vec![vec!["2".to_string()]]);
+        let results = db
+            .query("SELECT count(*) FROM code_blocks", "*", 10)
+            .unwrap();
+        assert_eq!(results, vec![vec!["0".to_string()]]);
+
+        // Inserting an indexed path is a conflict, other failures are not.
+        let err = db.insert_documents(&[doc1]).unwrap_err();
+        assert!(is_conflict(err.as_ref()));
+        let mut doc3 = create_test_document("doc3");
+        db.execute_batch("DROP TABLE code_blocks").unwrap();
+        doc3.code_blocks = vec![CodeBlock::new("sh".to_string(), "ls".to_string(), 1, 3)];
+        let err = db.insert_documents(&[doc3]).unwrap_err();
+        assert!(!is_conflict(err.as_ref()));
+
+        cleanup_db(&db_path);
+    }
+
+    #[test]
+    fn test_set_backlinks() {
+        let temp_dir = std::env::temp_dir();
+        let db_path = temp_dir.join(format!(
+            "test_mdb_{}_{}.duckdb",
+            std::process::id(),
+            get_unique_id()
+        ));
+        let db = Database::new(&db_path).unwrap();
+
+        let doc1 = create_test_document("doc1");
+        let doc2 = create_test_document("doc2");
+        db.insert_documents(&[doc1.clone(), doc2.clone()]).unwrap();
+        db.set_backlinks("notes", &[(doc2.path.clone(), vec![doc1.path.clone()])])
+            .unwrap();
+
+        let results = db
+            .query(
+                "SELECT name, backlinks, words FROM documents ORDER BY name",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                vec!["doc1".to_string(), "[]".to_string(), "3".to_string()],
+                vec![
+                    "doc2".to_string(),
+                    format!(r#"["{}"]"#, doc1.path),
+                    "3".to_string()
+                ],
+            ]
+        );
 
         cleanup_db(&db_path);
     }
@@ -1,72 +1,1077 @@
-use gray_matter::engine::YAML;
+use crate::dates::PeriodicFormats;
 use gray_matter::Matter;
+use gray_matter::engine::{Engine, JSON, TOML, YAML};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, LazyLock};
 
-static WIKILINK_REGEX: &str = r"\[\[([^\]]+)\]\]";
-static EMBED_REGEX: &str = r"!\[\[([^\]]+)\]\]";
-static TAG_REGEX: &str = r"#[\w\-/]+";
+static WIKILINK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\[([^\]]+)\]\]").unwrap());
+static EMBED_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"!\[\[([^\]]+)\]\]").unwrap());
+static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"#[\w\-/]+").unwrap());
+static BLOCK_ID_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|\s)\^([A-Za-z0-9\-]+)$").unwrap());
+static INLINE_FIELD_LINE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^[ \t]*(?:[-*+][ \t]+(?:\[.\][ \t]+)?|\d+\.[ \t]+|>[ \t]*)?(\w[\w \-]*?)::[ \t]*(.*?)[ \t]*$").unwrap()
+});
+static INLINE_FIELD_BRACKET_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[(\w[\w \-]*?)::[ \t]*([^\]\n]*?)[ \t]*\]").unwrap());
+static INLINE_FIELD_PAREN_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\((\w[\w \-]*?)::[ \t]*([^)\n]*?)[ \t]*\)").unwrap());
+static CALLOUT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[!([\w\-]+)\]([+-]?)[ \t]*(.*)$").unwrap());
+static ERROR_LOCATION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:\s*at)?(?: byte \d+)? line (\d+),? column (\d+)").unwrap());
+static H1_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^#[ \t]+(.+?)[ \t#]*$").unwrap());
+static INLINE_CODE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"``[^\n]*?``|`[^`\n]+`").unwrap());
+static COMMENT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)%%(.*?)%%").unwrap());
+static HIGHLIGHT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"==([^=\s](?:[^\n]*?[^=\s])?)==").unwrap());
+static FOOTNOTE_DEFINITION_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[\^([^\]\s]+)\]:[ \t]*(.*)$").unwrap());
+static INLINE_FOOTNOTE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\^\[([^\]]+)\]").unwrap());
+
+/// Plain-text rewrites applied in order, each as `(pattern, replacement)`.
+static PLAIN_TEXT_PATTERNS: &[(&str, &str)] = &[
+    (r"(?s)%%.*?%%", ""),
+    (r"!\[\[[^\]]*\]\]", ""),
+    (r"!\[[^\]]*\]\([^)]*\)", ""),
+    (r"\[\[[^\]|]*\|([^\]]*)\]\]", "$1"),
+    (r"\[\[([^\]]*)\]\]", "$1"),
+    (r"\[([^\]]*)\]\([^)]*\)", "$1"),
+    (r"<[^>\n]+>", ""),
+    (
+        r"(?m)^[ \t]*(?:#{1,6}[ \t]+|>[ \t]?|[-*+][ \t]+(?:\[.\][ \t]+)?|\d+\.[ \t]+)",
+        "",
+    ),
+    (r"(?m)[ \t]+\^[A-Za-z0-9\-]+$", ""),
+    (r"\*\*|__|\*|~~|==|`", ""),
+];
+
+/// `PLAIN_TEXT_PATTERNS`, compiled once.
+static PLAIN_TEXT_RULES: LazyLock<Vec<(Regex, &str)>> = LazyLock::new(|| {
+    PLAIN_TEXT_PATTERNS
+        .iter()
+        .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), *replacement))
+        .collect()
+});
+
+const EXCERPT_CHARS: usize = 200;
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Optional extraction behaviour, off by default.
+#[derive(Clone, Default)]
+pub struct ExtractOptions {
+    /// Keep the text of `%%comments%%` in `ExtractedContent::comments`.
+    pub index_comments: bool,
+    /// Extra list fields, stored under each extractor's name.
+    pub extractors: Vec<Arc<dyn FieldExtractor>>,
+    /// File name formats of periodic notes, used to infer `file.date`.
+    pub periodic_formats: PeriodicFormats,
+    /// Leave the raw file out of `documents.content`; `body` and
+    /// `plain_text` are still stored.
+    pub skip_content: bool,
+}
+
+/// Pulls a named list of values out of a note body, for conventions the
+/// built-in extraction does not know about (ticket IDs, mentions, ...).
+pub trait FieldExtractor: Send + Sync {
+    fn name(&self) -> &str;
+    fn extract(&self, body: &str) -> Vec<String>;
+}
+
+/// Collects one capture group of every match of a regex.
+pub struct RegexExtractor {
+    name: String,
+    regex: Regex,
+    group: usize,
+}
+
+impl RegexExtractor {
+    /// Uses `group` when given, otherwise the first capture group if the
+    /// pattern has one and the whole match if not.
+    pub fn new(name: &str, pattern: &str, group: Option<usize>) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("extractor '{}': {}", name, e))?;
+        let group = group.unwrap_or(if regex.captures_len() > 1 { 1 } else { 0 });
+        if group >= regex.captures_len() {
+            return Err(format!(
+                "extractor '{}': pattern has no capture group {}",
+                name, group
+            ));
+        }
+        Ok(RegexExtractor {
+            name: name.to_string(),
+            regex,
+            group,
+        })
+    }
+}
+
+impl FieldExtractor for RegexExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extract(&self, body: &str) -> Vec<String> {
+        self.regex
+            .captures_iter(body)
+            .filter_map(|cap| cap.get(self.group).map(|m| m.as_str().to_string()))
+            .collect()
+    }
+}
 
 pub struct Extractor;
 
 impl Extractor {
-    pub fn extract(content: &str) -> ExtractedContent {
-        let (frontmatter, content_without_fm) = Self::parse_frontmatter(content);
-        let tags = Self::extract_tags(&content_without_fm);
-        let links = Self::extract_wikilinks(&content_without_fm);
-        let embeds = Self::extract_embeds(&content_without_fm);
+    pub fn extract(content: &str, options: &ExtractOptions) -> ExtractedContent {
+        let Frontmatter {
+            data: frontmatter,
+            body: content_without_fm,
+            format: frontmatter_format,
+            error: frontmatter_error,
+        } = Self::parse_frontmatter(content);
+        // Tags, links and fields written inside %%comments%% are not part of
+        // the note as Obsidian renders it.
+        let visible = Self::strip_comments(&content_without_fm);
+        let inline_tags = Self::extract_tags(&Self::blank_code(&visible));
+        let frontmatter_tags = Self::frontmatter_list(&frontmatter, &["tags", "tag"]);
+        let (tags, tag_sources) = Self::merge_tags(&frontmatter_tags, &inline_tags);
+        let aliases = Self::frontmatter_list(&frontmatter, &["aliases", "alias"]);
+        let links = Self::extract_wikilinks(&visible);
+        let embeds = Self::extract_embeds(&visible);
+        let highlights = Self::extract_highlights(&visible);
+        let footnotes = Self::extract_footnotes(&visible);
+        let comments = if options.index_comments {
+            Self::extract_comments(&content_without_fm)
+        } else {
+            Vec::new()
+        };
+        let custom_fields = Self::extract_custom_fields(&visible, &options.extractors);
+        let inline_fields = Self::extract_inline_fields(&visible);
+        let (properties, property_sources) = Self::merge_properties(&frontmatter, &inline_fields);
+        let blocks = Self::extract_blocks(content, &content_without_fm);
+        let callouts = Self::extract_callouts(content, &content_without_fm);
+        let code_blocks = Self::extract_code_blocks(content, &content_without_fm);
+        let plain_text = Self::to_plain_text(&content_without_fm);
+        let stats = Self::compute_stats(content, &content_without_fm, &plain_text, &frontmatter);
 
         ExtractedContent {
             full_content: content.to_string(),
-            frontmatter,
+            body: content_without_fm,
+            plain_text,
+            frontmatter_format,
+            errors: frontmatter_error.into_iter().collect(),
+            properties,
+            property_sources,
             tags,
+            tag_sources,
+            aliases,
             links,
             embeds,
+            highlights,
+            footnotes,
+            comments,
+            custom_fields,
+            blocks,
+            callouts,
+            code_blocks,
+            stats,
+        }
+    }
+
+    /// Renders markdown as plain text: code blocks, markup, link targets,
+    /// embeds and block IDs are dropped, while link and heading text is kept.
+    pub fn to_plain_text(body: &str) -> String {
+        let mut text: String = Self::fenced_lines(body)
+            .into_iter()
+            .filter(|(_, in_block)| !in_block)
+            .map(|(line, _)| line)
+            .collect();
+        for (re, replacement) in PLAIN_TEXT_RULES.iter() {
+            text = re.replace_all(&text, *replacement).into_owned();
+        }
+        text.lines()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+
+    /// Counts words, treating every CJK character as a word of its own since
+    /// those scripts do not separate words with spaces.
+    pub fn count_words(text: &str) -> usize {
+        let mut words = 0;
+        let mut in_word = false;
+        for c in text.chars() {
+            if Self::is_cjk(c) {
+                words += 1;
+                in_word = false;
+            } else if c.is_alphanumeric() {
+                if !in_word {
+                    words += 1;
+                    in_word = true;
+                }
+            } else if c.is_whitespace() {
+                in_word = false;
+            }
+        }
+        words
+    }
+
+    fn is_cjk(c: char) -> bool {
+        matches!(c,
+            '\u{3040}'..='\u{30FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{AC00}'..='\u{D7AF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{20000}'..='\u{2FA1F}')
+    }
+
+    /// The title is the frontmatter `title` when present, otherwise the
+    /// first level-one heading of the body outside code blocks.
+    fn extract_title(body: &str, frontmatter: &Value) -> Option<String> {
+        if let Some(title) = frontmatter.get("title").and_then(|t| t.as_str()) {
+            return Some(title.to_string());
+        }
+        Self::fenced_lines(body)
+            .into_iter()
+            .filter(|(_, in_block)| !in_block)
+            .find_map(|(line, _)| H1_REGEX.captures(line))
+            .map(|cap| Self::to_plain_text(&cap[1]))
+            .filter(|title| !title.is_empty())
+    }
+
+    fn compute_stats(content: &str, body: &str, plain: &str, frontmatter: &Value) -> DocumentStats {
+        let title = Self::extract_title(body, frontmatter);
+        let words = Self::count_words(plain);
+
+        let mut paragraphs = plain.lines().filter(|line| !line.is_empty()).peekable();
+        if title.is_some() && paragraphs.peek().map(|line| Some(*line)) == Some(title.as_deref()) {
+            paragraphs.next();
+        }
+        let joined = paragraphs.collect::<Vec<_>>().join(" ");
+        let excerpt = if joined.chars().count() > EXCERPT_CHARS {
+            let cut: String = joined.chars().take(EXCERPT_CHARS).collect();
+            format!("{}…", cut.trim_end())
+        } else {
+            joined
+        };
+
+        DocumentStats {
+            words,
+            chars: plain.chars().count(),
+            lines: content.lines().count(),
+            reading_time: words.div_ceil(WORDS_PER_MINUTE),
+            title,
+            excerpt,
+        }
+    }
+
+    /// Collects the values of the first present frontmatter key in `keys`,
+    /// accepting either a YAML list or a comma/space separated string.
+    fn frontmatter_list(frontmatter: &Value, keys: &[&str]) -> Vec<String> {
+        let Some(value) = keys.iter().find_map(|key| frontmatter.get(key)) else {
+            return Vec::new();
+        };
+
+        let is_tags = keys.contains(&"tags");
+        // A list already separates its items, so only a scalar string is split
+        // into several values. Aliases may legitimately contain spaces, tags may not.
+        let raw: Vec<String> = match value {
+            Value::Array(items) => items
+                .iter()
+                .filter_map(|item| match item {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            Value::String(s) if is_tags => s
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .map(|p| p.to_string())
+                .collect(),
+            Value::String(s) => s.split(',').map(|p| p.to_string()).collect(),
+            Value::Number(n) => vec![n.to_string()],
+            _ => Vec::new(),
+        };
+
+        raw.iter()
+            .map(|item| item.trim())
+            .map(|item| {
+                if is_tags {
+                    item.trim_start_matches('#')
+                } else {
+                    item
+                }
+            })
+            .filter(|item| !item.is_empty())
+            .map(|item| item.to_string())
+            .collect()
+    }
+
+    fn merge_tags(
+        frontmatter_tags: &[String],
+        inline_tags: &[String],
+    ) -> (Vec<String>, BTreeMap<String, Vec<Source>>) {
+        let mut tags: Vec<String> = Vec::new();
+        let mut sources: BTreeMap<String, Vec<Source>> = BTreeMap::new();
+
+        let tagged = frontmatter_tags
+            .iter()
+            .map(|t| (t, Source::Frontmatter))
+            .chain(inline_tags.iter().map(|t| (t, Source::Inline)));
+
+        // Obsidian treats tags case-insensitively; the first spelling wins.
+        for (tag, source) in tagged {
+            let tag = tags
+                .iter()
+                .find(|t| t.to_lowercase() == tag.to_lowercase())
+                .cloned()
+                .unwrap_or_else(|| {
+                    tags.push(tag.clone());
+                    tag.clone()
+                });
+            let entry = sources.entry(tag).or_default();
+            if !entry.contains(&source) {
+                entry.push(source);
+            }
+        }
+
+        (tags, sources)
+    }
+
+    /// Extracts Dataview inline fields: `key:: value` on its own line (also
+    /// inside list items and quotes), `[key:: value]` and `(key:: value)`.
+    /// Code blocks and inline code are skipped, so `a::b` in a snippet is
+    /// not taken for a field.
+    fn extract_inline_fields(content: &str) -> Vec<(String, Value)> {
+        let masked = Self::blank_code(content);
+        let mut fields = Vec::new();
+        for (line, masked_line) in content.lines().zip(masked.lines()) {
+            let field = |cap: regex::Captures| {
+                let key = line[cap.get(1).unwrap().range()].trim().to_string();
+                (key, Self::inline_value(&line[cap.get(2).unwrap().range()]))
+            };
+            if let Some(cap) = INLINE_FIELD_LINE_REGEX.captures(masked_line) {
+                fields.push(field(cap));
+                continue;
+            }
+            for re in [&INLINE_FIELD_BRACKET_REGEX, &INLINE_FIELD_PAREN_REGEX] {
+                fields.extend(re.captures_iter(masked_line).map(field));
+            }
+        }
+        fields
+    }
+
+    fn inline_value(raw: &str) -> Value {
+        match serde_json::from_str::<Value>(raw) {
+            Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+            _ => Value::String(raw.to_string()),
+        }
+    }
+
+    /// Merges inline fields into the frontmatter object. A key declared more
+    /// than once, inline or across both origins, becomes a list in
+    /// declaration order with frontmatter values first.
+    fn merge_properties(
+        frontmatter: &Value,
+        inline_fields: &[(String, Value)],
+    ) -> (Value, BTreeMap<String, Vec<Source>>) {
+        let mut sources: BTreeMap<String, Vec<Source>> = BTreeMap::new();
+        let mut properties = match frontmatter {
+            Value::Object(map) => {
+                for key in map.keys() {
+                    sources.insert(key.clone(), vec![Source::Frontmatter]);
+                }
+                map.clone()
+            }
+            _ if inline_fields.is_empty() => return (frontmatter.clone(), sources),
+            _ => serde_json::Map::new(),
+        };
+
+        for (key, value) in inline_fields {
+            match properties.get_mut(key) {
+                Some(Value::Array(items)) => items.push(value.clone()),
+                Some(existing) => {
+                    let first = existing.take();
+                    *existing = Value::Array(vec![first, value.clone()]);
+                }
+                None => {
+                    properties.insert(key.clone(), value.clone());
+                }
+            }
+            let entry = sources.entry(key.clone()).or_default();
+            if !entry.contains(&Source::Inline) {
+                entry.push(Source::Inline);
+            }
+        }
+
+        (Value::Object(properties), sources)
+    }
+
+    /// Finds `^block-id` markers. An ID ending a paragraph line names the whole
+    /// paragraph, one ending a list item names just that item, and one on its
+    /// own line names the block right above it (tables, quotes, code).
+    /// Line numbers are 1-based and relative to the full file.
+    fn extract_blocks(content: &str, body: &str) -> Vec<Block> {
+        let line_offset = Self::body_line_offset(content, body);
+
+        let lines: Vec<&str> = body.lines().collect();
+        let mut blocks = Vec::new();
+        let mut run_start: Option<usize> = None;
+        let mut prev_run: Option<(usize, usize)> = None;
+        let mut in_fence = false;
+
+        for (i, line) in lines.iter().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() && !in_fence {
+                if let Some(start) = run_start.take() {
+                    prev_run = Some((start, i - 1));
+                }
+                continue;
+            }
+            let start = *run_start.get_or_insert(i);
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence {
+                continue;
+            }
+
+            let Some(cap) = BLOCK_ID_REGEX.captures(line.trim_end()) else {
+                continue;
+            };
+            let marker = cap.get(0).unwrap();
+            let line_text = line[..marker.start()].trim();
+
+            let (first, last) = if !line_text.is_empty() {
+                if Self::is_list_item(line_text) {
+                    (i, i)
+                } else {
+                    (start, i)
+                }
+            } else if start < i {
+                (start, i - 1)
+            } else if let Some(run) = prev_run {
+                run
+            } else {
+                continue;
+            };
+
+            let text = (first..=last)
+                .map(|n| {
+                    if n == i {
+                        line_text
+                    } else {
+                        lines[n].trim_end()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            blocks.push(Block {
+                id: cap[1].to_string(),
+                text: text.trim().to_string(),
+                start_line: first + line_offset + 1,
+                end_line: last + line_offset + 1,
+            });
         }
+
+        blocks
     }
 
-    fn parse_frontmatter(content: &str) -> (Value, String) {
-        let matter = Matter::<YAML>::new();
-        match matter.parse::<Value>(content) {
-            Ok(result) => {
-                let frontmatter = result
-                    .data
-                    .map(|v| serde_json::to_value(v).unwrap_or(Value::Null))
-                    .unwrap_or(Value::Null);
-                (frontmatter, result.content)
+    /// Finds Obsidian callouts (`> [!type]+ Title`), including callouts nested
+    /// inside other callouts. A callout's body holds its following quoted
+    /// lines with its own `>` markers removed, nested callouts included.
+    fn extract_callouts(content: &str, body: &str) -> Vec<Callout> {
+        let line_offset = Self::body_line_offset(content, body);
+
+        let mut callouts: Vec<Callout> = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        let mut in_fence = false;
+
+        for (i, line) in body.lines().enumerate() {
+            let line_number = i + line_offset + 1;
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+            }
+            let (depth, text) = if in_fence && open.is_empty() {
+                (0, line)
+            } else {
+                Self::strip_quote(line, usize::MAX)
+            };
+
+            while let Some(&last) = open.last() {
+                if callouts[last].depth <= depth {
+                    break;
+                }
+                open.pop();
+            }
+
+            if depth > 0
+                && let Some(cap) = CALLOUT_REGEX.captures(text.trim_end())
+            {
+                while open
+                    .last()
+                    .is_some_and(|&last| callouts[last].depth >= depth)
+                {
+                    open.pop();
+                }
+                let title = cap[3].trim();
+                for &index in &open {
+                    let (_, nested) = Self::strip_quote(line, callouts[index].depth);
+                    Self::push_body_line(&mut callouts[index], nested, line_number);
+                }
+                callouts.push(Callout {
+                    kind: cap[1].to_lowercase(),
+                    title: (!title.is_empty()).then(|| title.to_string()),
+                    fold: match &cap[2] {
+                        "+" => Some("open".to_string()),
+                        "-" => Some("closed".to_string()),
+                        _ => None,
+                    },
+                    body: String::new(),
+                    depth,
+                    parent: open.last().copied(),
+                    start_line: line_number,
+                    end_line: line_number,
+                });
+                open.push(callouts.len() - 1);
+                continue;
+            }
+
+            for &index in &open {
+                let (_, nested) = Self::strip_quote(line, callouts[index].depth);
+                Self::push_body_line(&mut callouts[index], nested, line_number);
             }
-            Err(_) => (Value::Null, content.to_string()),
         }
+
+        for callout in &mut callouts {
+            callout.body = callout.body.trim_end().to_string();
+        }
+        callouts
+    }
+
+    /// Finds fenced code blocks opened by three or more backticks or tildes.
+    /// An unclosed fence runs to the end of the note, as in CommonMark.
+    fn extract_code_blocks(content: &str, body: &str) -> Vec<CodeBlock> {
+        let line_offset = Self::body_line_offset(content, body);
+        let mut code_blocks = Vec::new();
+        let mut open: Option<(String, usize, String, Vec<&str>)> = None;
+        let mut last_line = 0;
+
+        for (i, line) in body.lines().enumerate() {
+            let line_number = i + line_offset + 1;
+            last_line = line_number;
+
+            match open.as_mut() {
+                None => {
+                    if let Some((fence, info)) = Self::code_fence(line) {
+                        let info = info.trim().to_string();
+                        open = Some((fence.to_string(), line_number, info, Vec::new()));
+                    }
+                }
+                Some((opening, _, _, _)) if Self::closes_fence(line, opening) => {
+                    let (_, start_line, info, lines) = open.take().unwrap();
+                    code_blocks.push(CodeBlock::new(
+                        info,
+                        lines.join("\n"),
+                        start_line,
+                        line_number,
+                    ));
+                }
+                Some((_, _, _, lines)) => lines.push(line),
+            }
+        }
+
+        if let Some((_, start_line, info, lines)) = open {
+            code_blocks.push(CodeBlock::new(
+                info,
+                lines.join("\n"),
+                start_line,
+                last_line,
+            ));
+        }
+        code_blocks
+    }
+
+    /// Splits a fence line, three or more backticks or tildes, into the
+    /// fence and the info string after it.
+    fn code_fence(line: &str) -> Option<(&str, &str)> {
+        let trimmed = line.trim_start();
+        let fence_len = trimmed
+            .chars()
+            .take_while(|&c| c == '`' || c == '~')
+            .count();
+        let fence = &trimmed[..fence_len];
+        let is_fence = fence_len >= 3 && fence.chars().all(|c| c == fence.as_bytes()[0] as char);
+        is_fence.then(|| (fence, &trimmed[fence_len..]))
+    }
+
+    fn closes_fence(line: &str, opening: &str) -> bool {
+        matches!(Self::code_fence(line), Some((fence, rest)) if fence.starts_with(opening) && rest.trim().is_empty())
+    }
+
+    /// The lines of `body`, line breaks included, each with whether it is
+    /// part of a fenced code block, fences included.
+    fn fenced_lines(body: &str) -> Vec<(&str, bool)> {
+        let mut open: Option<&str> = None;
+        body.split_inclusive('\n')
+            .map(|line| {
+                let in_block = match open {
+                    Some(opening) => {
+                        if Self::closes_fence(line, opening) {
+                            open = None;
+                        }
+                        true
+                    }
+                    None => {
+                        open = Self::code_fence(line).map(|(fence, _)| fence);
+                        open.is_some()
+                    }
+                };
+                (line, in_block)
+            })
+            .collect()
+    }
+
+    /// Replaces fenced code blocks and inline code spans with spaces, keeping
+    /// line breaks and byte offsets so a match found in the result can be
+    /// read back from `body`.
+    fn blank_code(body: &str) -> String {
+        let blank = |text: &str| -> String {
+            text.chars()
+                .map(|c| match c {
+                    '\n' | '\r' => c.to_string(),
+                    _ => " ".repeat(c.len_utf8()),
+                })
+                .collect()
+        };
+
+        let mut blanked = String::with_capacity(body.len());
+        for (line, in_block) in Self::fenced_lines(body) {
+            if in_block {
+                blanked.push_str(&blank(line));
+            } else {
+                blanked.push_str(
+                    &INLINE_CODE_REGEX.replace_all(line, |cap: &regex::Captures| blank(&cap[0])),
+                );
+            }
+        }
+        blanked
+    }
+
+    fn push_body_line(callout: &mut Callout, text: &str, line_number: usize) {
+        if !callout.body.is_empty() || !text.trim().is_empty() {
+            callout.body.push_str(text.trim_end());
+            callout.body.push('\n');
+        }
+        callout.end_line = line_number;
+    }
+
+    /// Removes up to `max_depth` leading `>` markers, returning how many were
+    /// removed and the rest of the line.
+    fn strip_quote(line: &str, max_depth: usize) -> (usize, &str) {
+        let mut depth = 0;
+        let mut rest = line;
+        while depth < max_depth {
+            let Some(after) = rest.trim_start().strip_prefix('>') else {
+                break;
+            };
+            rest = after.strip_prefix(' ').unwrap_or(after);
+            depth += 1;
+        }
+        (depth, rest)
+    }
+
+    /// Number of lines before the body, i.e. taken up by frontmatter. The
+    /// body is a suffix of the file by lines, though not necessarily by bytes
+    /// as the frontmatter parser rebuilds it line by line.
+    fn body_line_offset(content: &str, body: &str) -> usize {
+        content.lines().count().saturating_sub(body.lines().count())
+    }
+
+    fn is_list_item(line: &str) -> bool {
+        let marker = line.trim_start_matches(|c: char| c.is_ascii_digit());
+        line.starts_with(['-', '*', '+']) || (marker.len() < line.len() && marker.starts_with('.'))
+    }
+
+    /// Detects the frontmatter format from the opening delimiter: `---` for
+    /// YAML, `+++` for TOML, and `;;;` or a bare `{ ... }` object for JSON.
+    ///
+    /// Invalid frontmatter leaves the data null and the body untouched, and
+    /// records the parse error instead of failing the whole note.
+    fn parse_frontmatter(content: &str) -> Frontmatter {
+        let first_line = content.lines().next().unwrap_or("").trim_end();
+        let (format, parsed) = match first_line {
+            "+++" => (
+                FrontmatterFormat::Toml,
+                Self::parse_with::<TOML>(content, "+++"),
+            ),
+            ";;;" => (
+                FrontmatterFormat::Json,
+                Self::parse_with::<JSON>(content, ";;;"),
+            ),
+            _ if content.starts_with('{') => (
+                FrontmatterFormat::Json,
+                Ok(Self::parse_json_object(content)),
+            ),
+            _ => (
+                FrontmatterFormat::Yaml,
+                Self::parse_with::<YAML>(content, "---"),
+            ),
+        };
+
+        match parsed {
+            Ok((data, body)) => Frontmatter {
+                format: (!data.is_null()).then_some(format),
+                data,
+                body,
+                error: None,
+            },
+            Err(message) => Frontmatter {
+                data: Value::Null,
+                body: content.to_string(),
+                format: None,
+                error: Some(Self::frontmatter_error(format, &message)),
+            },
+        }
+    }
+
+    fn parse_with<T: Engine>(content: &str, delimiter: &str) -> Result<(Value, String), String> {
+        let mut matter = Matter::<T>::new();
+        matter.delimiter = delimiter.to_string();
+        let result = matter.parse::<Value>(content).map_err(|e| e.to_string())?;
+        let frontmatter = result
+            .data
+            .map(|v| serde_json::to_value(v).unwrap_or(Value::Null))
+            .unwrap_or(Value::Null);
+        Ok((frontmatter, result.content))
+    }
+
+    /// Parses Hugo-style JSON frontmatter: a JSON object at the very start of
+    /// the file, followed by the body. Anything else is treated as body text.
+    fn parse_json_object(content: &str) -> (Value, String) {
+        let mut stream = serde_json::Deserializer::from_str(content).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(frontmatter @ Value::Object(_))) => {
+                let rest = &content[stream.byte_offset()..];
+                let body = rest
+                    .strip_prefix("\r\n")
+                    .or_else(|| rest.strip_prefix('\n'))
+                    .unwrap_or(rest);
+                (frontmatter, body.to_string())
+            }
+            _ => (Value::Null, content.to_string()),
+        }
+    }
+
+    /// Turns a parser message into an [`ExtractError`]. Parsers report
+    /// positions within the frontmatter text, so the opening delimiter line
+    /// is added to make the line relative to the file.
+    fn frontmatter_error(format: FrontmatterFormat, message: &str) -> ExtractError {
+        let message = message.trim_start_matches("Deserialize error: ").trim();
+        let location = ERROR_LOCATION_REGEX.captures(message).map(|cap| {
+            let line: usize = cap[1].parse().unwrap_or(0);
+            let column: usize = cap[2].parse().unwrap_or(0);
+            (line + 1, column)
+        });
+
+        // TOML puts a source excerpt between the location and the reason.
+        let reason = if message.contains('\n') {
+            message
+                .lines()
+                .rev()
+                .find(|l| !l.trim().is_empty())
+                .unwrap_or(message)
+        } else {
+            message
+        };
+        let reason = ERROR_LOCATION_REGEX.replace(reason, "").trim().to_string();
+
+        ExtractError {
+            kind: format!("{}-frontmatter", format.as_str()),
+            message: reason,
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+        }
+    }
+
+    /// Runs each configured extractor, keeping the first occurrence of every
+    /// value. Extractors without matches still get an empty list.
+    fn extract_custom_fields(
+        body: &str,
+        extractors: &[Arc<dyn FieldExtractor>],
+    ) -> BTreeMap<String, Vec<String>> {
+        extractors
+            .iter()
+            .map(|extractor| {
+                let mut values: Vec<String> = Vec::new();
+                for value in extractor.extract(body) {
+                    if !values.contains(&value) {
+                        values.push(value);
+                    }
+                }
+                (extractor.name().to_string(), values)
+            })
+            .collect()
+    }
+
+    /// Blanks out `%%comments%%`, keeping their line breaks so positions in
+    /// the rest of the body do not move. `%%` inside code is left alone.
+    fn strip_comments(body: &str) -> String {
+        let masked = Self::blank_code(body);
+        let mut stripped = String::with_capacity(body.len());
+        let mut last = 0;
+        for comment in COMMENT_REGEX.find_iter(&masked) {
+            stripped.push_str(&body[last..comment.start()]);
+            stripped.push_str(&"\n".repeat(body[comment.range()].matches('\n').count()));
+            last = comment.end();
+        }
+        stripped.push_str(&body[last..]);
+        stripped
+    }
+
+    fn extract_comments(body: &str) -> Vec<String> {
+        let masked = Self::blank_code(body);
+        COMMENT_REGEX
+            .captures_iter(&masked)
+            .map(|cap| body[cap.get(1).unwrap().range()].trim().to_string())
+            .filter(|comment| !comment.is_empty())
+            .collect()
+    }
+
+    fn extract_highlights(body: &str) -> Vec<String> {
+        let masked = Self::blank_code(body);
+        HIGHLIGHT_REGEX
+            .captures_iter(&masked)
+            .map(|cap| body[cap.get(1).unwrap().range()].to_string())
+            .collect()
+    }
+
+    /// Collects `[^label]: text` definitions, including indented continuation
+    /// lines, followed by inline `^[text]` footnotes.
+    fn extract_footnotes(body: &str) -> Vec<Footnote> {
+        let mut footnotes: Vec<Footnote> = Vec::new();
+        let mut in_definition = false;
+
+        for line in body.lines() {
+            if let Some(cap) = FOOTNOTE_DEFINITION_REGEX.captures(line) {
+                footnotes.push(Footnote {
+                    label: Some(cap[1].to_string()),
+                    text: cap[2].trim().to_string(),
+                });
+                in_definition = true;
+            } else if in_definition && line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+                let footnote = footnotes.last_mut().unwrap();
+                if !footnote.text.is_empty() {
+                    footnote.text.push(' ');
+                }
+                footnote.text.push_str(line.trim());
+            } else {
+                in_definition = false;
+            }
+        }
+
+        footnotes.extend(
+            INLINE_FOOTNOTE_REGEX
+                .captures_iter(body)
+                .map(|cap| Footnote {
+                    label: None,
+                    text: cap[1].trim().to_string(),
+                }),
+        );
+        footnotes
     }
 
     fn extract_tags(content: &str) -> Vec<String> {
-        let re = Regex::new(TAG_REGEX).unwrap();
-        re.find_iter(content)
+        TAG_REGEX
+            .find_iter(content)
             .map(|m| m.as_str().trim_start_matches('#').to_string())
             .collect()
     }
 
     fn extract_wikilinks(content: &str) -> Vec<String> {
-        let re = Regex::new(WIKILINK_REGEX).unwrap();
-        re.captures_iter(content)
+        WIKILINK_REGEX
+            .captures_iter(content)
             .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
             .collect()
     }
 
     fn extract_embeds(content: &str) -> Vec<String> {
-        let re = Regex::new(EMBED_REGEX).unwrap();
-        re.captures_iter(content)
+        EMBED_REGEX
+            .captures_iter(content)
             .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
             .collect()
     }
 }
 
+/// Syntax the frontmatter was written in, kept so edits can write it back
+/// the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontmatterFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl FrontmatterFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrontmatterFormat::Yaml => "yaml",
+            FrontmatterFormat::Toml => "toml",
+            FrontmatterFormat::Json => "json",
+        }
+    }
+}
+
+struct Frontmatter {
+    data: Value,
+    body: String,
+    format: Option<FrontmatterFormat>,
+    error: Option<ExtractError>,
+}
+
+/// A problem found while extracting a note. Extraction keeps going and the
+/// error is stored with the document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtractError {
+    /// What failed, e.g. `yaml-frontmatter`.
+    pub kind: String,
+    pub message: String,
+    /// 1-based position in the file, when the parser reports one.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(
+                    f,
+                    "{} (line {}, column {}): {}",
+                    self.kind, line, column, self.message
+                )
+            }
+            _ => write!(f, "{}: {}", self.kind, self.message),
+        }
+    }
+}
+
+/// Where a tag or property was declared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Frontmatter,
+    Inline,
+}
+
+/// Size and summary figures computed from a note's body.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DocumentStats {
+    pub words: usize,
+    /// Characters of the plain-text rendering.
+    pub chars: usize,
+    /// Lines of the whole file, frontmatter included.
+    pub lines: usize,
+    /// Estimated minutes at 200 words per minute, rounded up.
+    pub reading_time: usize,
+    pub title: Option<String>,
+    pub excerpt: String,
+}
+
+/// An Obsidian callout such as `> [!warning]- Title`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Callout {
+    /// Callout type, lowercased (`note`, `warning`, `question`, ...).
+    pub kind: String,
+    pub title: Option<String>,
+    /// `open` for `+`, `closed` for `-`, `None` when not foldable.
+    pub fold: Option<String>,
+    pub body: String,
+    /// Quote depth, 1 for a top-level callout.
+    pub depth: usize,
+    /// Index of the enclosing callout within the same note.
+    pub parent: Option<usize>,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// A fenced code block. Lines cover the fences themselves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CodeBlock {
+    /// First word of the info string, lowercased.
+    pub language: Option<String>,
+    pub info: String,
+    pub text: String,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+impl CodeBlock {
+    pub fn new(info: String, text: String, start_line: usize, end_line: usize) -> Self {
+        CodeBlock {
+            language: info.split_whitespace().next().map(|l| l.to_lowercase()),
+            info,
+            text,
+            start_line,
+            end_line,
+        }
+    }
+}
+
+/// A footnote definition `[^label]: text`, or an inline `^[text]` footnote
+/// when `label` is `None`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Footnote {
+    pub label: Option<String>,
+    pub text: String,
+}
+
+/// A block identified by a `^block-id` marker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    pub id: String,
+    pub text: String,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
 pub struct ExtractedContent {
     pub full_content: String,
-    pub frontmatter: Value,
+    /// The note without its frontmatter.
+    pub body: String,
+    /// `body` rendered by `Extractor::to_plain_text`.
+    pub plain_text: String,
+    pub frontmatter_format: Option<FrontmatterFormat>,
+    pub errors: Vec<ExtractError>,
+    pub properties: Value,
+    pub property_sources: BTreeMap<String, Vec<Source>>,
     pub tags: Vec<String>,
+    pub tag_sources: BTreeMap<String, Vec<Source>>,
+    pub aliases: Vec<String>,
     pub links: Vec<String>,
     pub embeds: Vec<String>,
+    pub highlights: Vec<String>,
+    pub footnotes: Vec<Footnote>,
+    /// Text of `%%comments%%`, only filled when `index_comments` is set.
+    pub comments: Vec<String>,
+    /// Values found by `ExtractOptions::extractors`, keyed by extractor name.
+    pub custom_fields: BTreeMap<String, Vec<String>>,
+    pub blocks: Vec<Block>,
+    pub callouts: Vec<Callout>,
+    pub code_blocks: Vec<CodeBlock>,
+    pub stats: DocumentStats,
 }
 
 #[cfg(test)]
@@ -76,9 +1081,9 @@ mod tests {
     #[test]
     fn test_extract_simple_content() {
         let content = "# Hello World\n\nThis is a test.";
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.full_content, content);
-        assert!(extracted.frontmatter.is_null());
+        assert!(extracted.properties.is_null());
         assert!(extracted.tags.is_empty());
         assert!(extracted.links.is_empty());
         assert!(extracted.embeds.is_empty());
@@ -94,15 +1099,10 @@ tags: [test, example]
 # Content
 
 This is the body."#;
-        let extracted = Extractor::extract(content);
-        assert!(!extracted.frontmatter.is_null());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(!extracted.properties.is_null());
         assert_eq!(
-            extracted
-                .frontmatter
-                .get("title")
-                .unwrap()
-                .as_str()
-                .unwrap(),
+            extracted.properties.get("title").unwrap().as_str().unwrap(),
             "Test Document"
         );
         assert_eq!(extracted.full_content, content);
@@ -111,7 +1111,7 @@ This is the body."#;
     #[test]
     fn test_extract_tags() {
         let content = "This has #tag1 and #tag-2 and #nested/tag";
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.tags.len(), 3);
         assert!(extracted.tags.contains(&"tag1".to_string()));
         assert!(extracted.tags.contains(&"tag-2".to_string()));
@@ -121,7 +1121,7 @@ This is the body."#;
     #[test]
     fn test_extract_wikilinks() {
         let content = "See [[architecture]] and [[performance-tips]] for more info.";
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.links.len(), 2);
         assert!(extracted.links.contains(&"architecture".to_string()));
         assert!(extracted.links.contains(&"performance-tips".to_string()));
@@ -130,32 +1130,38 @@ This is the body."#;
     #[test]
     fn test_extract_embeds() {
         let content = "Check this image: ![[mobile-app-mockup.png]] and ![[diagram.svg]]";
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.embeds.len(), 2);
-        assert!(extracted
-            .embeds
-            .contains(&"mobile-app-mockup.png".to_string()));
+        assert!(
+            extracted
+                .embeds
+                .contains(&"mobile-app-mockup.png".to_string())
+        );
         assert!(extracted.embeds.contains(&"diagram.svg".to_string()));
     }
 
     #[test]
     fn test_extract_wikilinks_with_aliases() {
         let content = "See [[architecture|System Architecture]] for details.";
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.links.len(), 1);
-        assert!(extracted
-            .links
-            .contains(&"architecture|System Architecture".to_string()));
+        assert!(
+            extracted
+                .links
+                .contains(&"architecture|System Architecture".to_string())
+        );
     }
 
     #[test]
     fn test_extract_wikilinks_with_headers() {
         let content = "See [[architecture#Overview]] for details.";
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.links.len(), 1);
-        assert!(extracted
-            .links
-            .contains(&"architecture#Overview".to_string()));
+        assert!(
+            extracted
+                .links
+                .contains(&"architecture#Overview".to_string())
+        );
     }
 
     #[test]
@@ -172,17 +1178,12 @@ Check [[architecture]] and [[api-design]].
 ![[mockup.png]]
 
 #project #mobile #ios"#;
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(extracted.links.len(), 3); // [[architecture]], [[api-design]], [[mockup.png]] from embed
         assert_eq!(extracted.embeds.len(), 1);
         assert_eq!(extracted.tags.len(), 3);
         assert_eq!(
-            extracted
-                .frontmatter
-                .get("title")
-                .unwrap()
-                .as_str()
-                .unwrap(),
+            extracted.properties.get("title").unwrap().as_str().unwrap(),
             "Mobile App"
         );
     }
@@ -193,16 +1194,16 @@ Check [[architecture]] and [[api-design]].
 ---
 
 Content here."#;
-        let extracted = Extractor::extract(content);
-        assert!(extracted.frontmatter.is_null());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(extracted.properties.is_null());
         assert_eq!(extracted.full_content, content);
     }
 
     #[test]
     fn test_extract_no_frontmatter() {
         let content = "--- not frontmatter\n\nContent";
-        let extracted = Extractor::extract(content);
-        assert!(extracted.frontmatter.is_null());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(extracted.properties.is_null());
     }
 
     #[test]
@@ -212,16 +1213,540 @@ invalid: yaml: [
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
-        assert!(extracted.frontmatter.is_null());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(extracted.properties.is_null());
+        assert_eq!(
+            extracted.errors,
+            vec![ExtractError {
+                kind: "yaml-frontmatter".to_string(),
+                message: "mapping values are not allowed in this context".to_string(),
+                line: Some(2),
+                column: Some(14),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_extract_invalid_toml_and_json_frontmatter() {
+        let extracted = Extractor::extract(
+            "+++\ntitle = \"ok\"\nbroken =\n+++\n\nBody",
+            &ExtractOptions::default(),
+        );
+        let error = &extracted.errors[0];
+        assert_eq!(error.kind, "toml-frontmatter");
+        assert_eq!((error.line, error.column), (Some(3), Some(9)));
+        assert!(!error.message.is_empty());
+        assert!(!error.message.contains('\n'));
+
+        let extracted =
+            Extractor::extract(";;;\n{\"a\": \n}\n;;;\nBody", &ExtractOptions::default());
+        let error = &extracted.errors[0];
+        assert_eq!(error.kind, "json-frontmatter");
+        assert_eq!(error.message, "expected value");
+        assert_eq!((error.line, error.column), (Some(3), Some(1)));
+        assert_eq!(
+            error.to_string(),
+            "json-frontmatter (line 3, column 1): expected value"
+        );
+
+        let fine = Extractor::extract("---\ntitle: fine\n---\n", &ExtractOptions::default());
+        assert!(fine.errors.is_empty());
     }
 
     #[test]
     fn test_extract_multiple_same_tags() {
         let content = "#tag #tag #tag";
-        let extracted = Extractor::extract(content);
-        assert_eq!(extracted.tags.len(), 3);
-        assert_eq!(extracted.tags.iter().filter(|t| *t == "tag").count(), 3);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.tags, vec!["tag".to_string()]);
+
+        let content = "#Project #project";
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.tags, vec!["Project".to_string()]);
+    }
+
+    #[test]
+    fn test_extract_frontmatter_tags_merged() {
+        let content = r##"---
+tags: [project, "#mobile"]
+---
+
+Inline #mobile and #ios"##;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.tags, vec!["project", "mobile", "ios"]);
+        assert_eq!(
+            extracted.tag_sources["mobile"],
+            vec![Source::Frontmatter, Source::Inline]
+        );
+        assert_eq!(extracted.tag_sources["project"], vec![Source::Frontmatter]);
+        assert_eq!(extracted.tag_sources["ios"], vec![Source::Inline]);
+    }
+
+    #[test]
+    fn test_extract_frontmatter_tag_string() {
+        let content = r#"---
+tag: "work, #urgent review"
+---
+
+Content"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.tags, vec!["work", "urgent", "review"]);
+    }
+
+    #[test]
+    fn test_extract_aliases() {
+        let content = r#"---
+aliases: [Sys Arch, arch]
+---
+
+Content"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.aliases, vec!["Sys Arch", "arch"]);
+
+        let content = "---\nalias: Overview, Intro\n---\n\nContent";
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.aliases, vec!["Overview", "Intro"]);
+
+        let content = "---\naliases: [\"Doe, Jane\", JD]\n---\n\nContent";
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.aliases, vec!["Doe, Jane", "JD"]);
+    }
+
+    #[test]
+    fn test_extract_inline_fields() {
+        let content = "status:: active\n- owner:: Alice\n\nTask [due:: 2024-02-01] and (rating:: 4).\n\nNot a field: http://example.com";
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        let props = &extracted.properties;
+        assert_eq!(props["status"], "active");
+        assert_eq!(props["owner"], "Alice");
+        assert_eq!(props["due"], "2024-02-01");
+        assert_eq!(props["rating"], 4);
+        assert_eq!(props.as_object().unwrap().len(), 4);
+        assert_eq!(extracted.property_sources["status"], vec![Source::Inline]);
+    }
+
+    #[test]
+    fn test_extract_inline_fields_repeated_keys() {
+        let content = r#"---
+status: draft
+---
+
+status:: active
+[reviewer:: Bob] [reviewer:: Carol] [reviewer:: Dan]"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        let props = &extracted.properties;
+        assert_eq!(props["status"], serde_json::json!(["draft", "active"]));
+        assert_eq!(
+            props["reviewer"],
+            serde_json::json!(["Bob", "Carol", "Dan"])
+        );
+        assert_eq!(
+            extracted.property_sources["status"],
+            vec![Source::Frontmatter, Source::Inline]
+        );
+    }
+
+    #[test]
+    fn test_extract_inline_fields_skips_code() {
+        let content = r#"Call `std::mem::swap` or `a::b` here.
+owner:: Alice
+
+```rust
+use std::collections::HashMap;
+let map: HashMap<String, u32> = HashMap::new();
+```
+
+~~~
+key:: not a field
+~~~
+[due:: 2024-02-01] and `(x:: y)`"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        let props = extracted.properties.as_object().unwrap();
+        assert_eq!(props.len(), 2);
+        assert_eq!(props["owner"], "Alice");
+        assert_eq!(props["due"], "2024-02-01");
+    }
+
+    #[test]
+    fn test_extract_tags_skips_code() {
+        let content = r#"Tagged #real, not `#inline` code.
+
+```c
+#include <stdio.h>
+#define MAX 10
+```
+"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.tags, vec!["real"]);
+    }
+
+    #[test]
+    fn test_extract_without_inline_fields_keeps_frontmatter() {
+        let extracted = Extractor::extract("# Plain note", &ExtractOptions::default());
+        assert!(extracted.properties.is_null());
+        assert!(extracted.property_sources.is_empty());
+    }
+
+    #[test]
+    fn test_extract_blocks() {
+        let content = r#"---
+title: Blocks
+---
+
+First line of a paragraph
+that ends with an id ^para-1
+
+- item one
+- item two ^item2
+
+| a | b |
+|---|---|
+
+^table1
+
+```
+not a block ^code
+```"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        let ids: Vec<&str> = extracted.blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, vec!["para-1", "item2", "table1"]);
+
+        let para = &extracted.blocks[0];
+        assert_eq!(para.text, "First line of a paragraph\nthat ends with an id");
+        assert_eq!((para.start_line, para.end_line), (5, 6));
+
+        let item = &extracted.blocks[1];
+        assert_eq!(item.text, "- item two");
+        assert_eq!((item.start_line, item.end_line), (9, 9));
+
+        let table = &extracted.blocks[2];
+        assert_eq!(table.text, "| a | b |\n|---|---|");
+        assert_eq!((table.start_line, table.end_line), (11, 12));
+    }
+
+    #[test]
+    fn test_to_plain_text() {
+        let body = "# Heading\n\n**Bold** and *em* with [[target|alias]], [[plain]] and [site](https://x.io).\n\n- [ ] task ^t1\n> quote ![[img.png]]";
+        assert_eq!(
+            Extractor::to_plain_text(body),
+            "Heading\n\nBold and em with alias, plain and site.\n\ntask\nquote"
+        );
+    }
+
+    #[test]
+    fn test_to_plain_text_drops_code_blocks() {
+        let body = "Install it:\n\n```bash\n# install deps\nnpm ci\n```\n\nThen run `mdb`.";
+        assert_eq!(
+            Extractor::to_plain_text(body),
+            "Install it:\n\n\nThen run mdb."
+        );
+    }
+
+    #[test]
+    fn test_count_words_cjk() {
+        assert_eq!(Extractor::count_words("Hello, world - again"), 3);
+        assert_eq!(Extractor::count_words("数据库"), 3);
+        assert_eq!(Extractor::count_words("使用 DuckDB 查询"), 5);
+        assert_eq!(Extractor::count_words(""), 0);
+    }
+
+    #[test]
+    fn test_extract_stats() {
+        let content = "---\nauthor: me\n---\n\n# My Note\n\nOne two three four.\n";
+        let stats = Extractor::extract(content, &ExtractOptions::default()).stats;
+        assert_eq!(stats.title.as_deref(), Some("My Note"));
+        assert_eq!(stats.words, 6);
+        assert_eq!(stats.lines, 7);
+        assert_eq!(stats.reading_time, 1);
+        assert_eq!(stats.excerpt, "One two three four.");
+        assert_eq!(
+            stats.chars,
+            "My Note\n\nOne two three four.".chars().count()
+        );
+
+        let stats = Extractor::extract(
+            "---\ntitle: From FM\n---\n\n# Heading",
+            &ExtractOptions::default(),
+        )
+        .stats;
+        assert_eq!(stats.title.as_deref(), Some("From FM"));
+
+        let stats = Extractor::extract(
+            "Setup:\n\n```sh\n# install deps\nnpm ci\n```\n\n# Usage `mdb`\n",
+            &ExtractOptions::default(),
+        )
+        .stats;
+        assert_eq!(stats.title.as_deref(), Some("Usage mdb"));
+        assert_eq!(
+            (stats.words, stats.excerpt.as_str()),
+            (3, "Setup: Usage mdb")
+        );
+
+        let stats =
+            Extractor::extract("```python\n# not a title\n```", &ExtractOptions::default()).stats;
+        assert_eq!(stats.title, None);
+        assert_eq!(stats.words, 0);
+
+        let stats = Extractor::extract("", &ExtractOptions::default()).stats;
+        assert_eq!(stats.title, None);
+        assert_eq!((stats.words, stats.reading_time), (0, 0));
+    }
+
+    #[test]
+    fn test_extract_excerpt_truncated() {
+        let content = "word ".repeat(100);
+        let stats = Extractor::extract(&content, &ExtractOptions::default()).stats;
+        assert!(stats.excerpt.ends_with('…'));
+        assert_eq!(stats.excerpt.chars().count(), EXCERPT_CHARS);
+        assert_eq!(stats.reading_time, 1);
+    }
+
+    #[test]
+    fn test_extract_yaml_frontmatter_format() {
+        let extracted =
+            Extractor::extract("---\ntitle: Y\n---\n\nBody", &ExtractOptions::default());
+        assert_eq!(extracted.frontmatter_format, Some(FrontmatterFormat::Yaml));
+
+        let extracted = Extractor::extract("Body without frontmatter", &ExtractOptions::default());
+        assert_eq!(extracted.frontmatter_format, None);
+    }
+
+    #[test]
+    fn test_extract_toml_frontmatter() {
+        let content = r#"+++
+title = "Hugo Post"
+tags = ["rust", "cli"]
+date = 2024-01-15
+
+[params]
+draft = true
++++
+
+# Body #inline"#;
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.frontmatter_format, Some(FrontmatterFormat::Toml));
+        assert_eq!(extracted.properties["title"], "Hugo Post");
+        assert_eq!(extracted.properties["date"], "2024-01-15");
+        assert_eq!(extracted.properties["params"]["draft"], true);
+        assert_eq!(extracted.tags, vec!["rust", "cli", "inline"]);
+        assert_eq!(extracted.stats.title.as_deref(), Some("Hugo Post"));
+    }
+
+    #[test]
+    fn test_extract_json_frontmatter() {
+        let content = ";;;\n{\"title\": \"Delimited\", \"count\": 3}\n;;;\n\nBody";
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.frontmatter_format, Some(FrontmatterFormat::Json));
+        assert_eq!(extracted.properties["title"], "Delimited");
+        assert_eq!(extracted.properties["count"], 3);
+
+        let content =
+            "{\n  \"title\": \"Bare\",\n  \"aliases\": [\"b\"]\n}\n# Heading\n\nText ^blk";
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(extracted.frontmatter_format, Some(FrontmatterFormat::Json));
+        assert_eq!(extracted.properties["title"], "Bare");
+        assert_eq!(extracted.aliases, vec!["b"]);
+        assert_eq!(extracted.blocks[0].start_line, 7);
+
+        let extracted = Extractor::extract("{not json} text", &ExtractOptions::default());
+        assert_eq!(extracted.frontmatter_format, None);
+        assert!(extracted.properties.is_null());
+    }
+
+    #[test]
+    fn test_extract_highlights_and_footnotes() {
+        let content = r#"Reading ==key idea== and ==another one==, but a == b is not.
+Claim[^1] with an inline note^[checked in 2024].
+
+[^1]: Source, page 12
+    continued on page 13
+[^note]: Second
+"#;
+        let result = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(result.highlights, vec!["key idea", "another one"]);
+        assert_eq!(
+            result.footnotes,
+            vec![
+                Footnote {
+                    label: Some("1".to_string()),
+                    text: "Source, page 12 continued on page 13".to_string(),
+                },
+                Footnote {
+                    label: Some("note".to_string()),
+                    text: "Second".to_string(),
+                },
+                Footnote {
+                    label: None,
+                    text: "checked in 2024".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_ignores_comments() {
+        let content = "Visible #shown [[Kept]]\n%%\nhidden #secret [[Dropped]]\nstatus:: draft\n%%\nInline %%#also-hidden%% text ^anchor\n";
+        let result = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(result.tags, vec!["shown"]);
+        assert_eq!(result.links, vec!["Kept"]);
+        assert!(result.properties.get("status").is_none());
+        assert!(result.comments.is_empty());
+        assert_eq!(result.blocks[0].id, "anchor");
+        assert!(!result.stats.excerpt.contains("secret"));
+
+        let options = ExtractOptions {
+            index_comments: true,
+            ..Default::default()
+        };
+        let result = Extractor::extract(content, &options);
+        assert_eq!(
+            result.comments,
+            vec!["hidden #secret [[Dropped]]\nstatus:: draft", "#also-hidden"]
+        );
+        assert_eq!(result.tags, vec!["shown"]);
+    }
+
+    #[test]
+    fn test_comments_and_highlights_skip_code() {
+        let content = r#"Use `a == b` and `x ==y== z`, then ==really==.
+Format with `%%d%%` #kept %%hidden%%
+
+```python
+print("%%s" % name)  # ==not a highlight==
+```
+"#;
+        let options = ExtractOptions {
+            index_comments: true,
+            ..Default::default()
+        };
+        let result = Extractor::extract(content, &options);
+        assert_eq!(result.highlights, vec!["really"]);
+        assert_eq!(result.comments, vec!["hidden"]);
+        assert_eq!(result.tags, vec!["kept"]);
+        assert_eq!(result.code_blocks.len(), 1);
+        assert!(result.code_blocks[0].text.contains("%%s"));
+    }
+
+    #[test]
+    fn test_extract_custom_fields() {
+        let options = ExtractOptions {
+            extractors: vec![
+                Arc::new(RegexExtractor::new("tickets", r"\b[A-Z]+-\d+\b", None).unwrap()),
+                Arc::new(RegexExtractor::new("mentions", r"@(\w+)", None).unwrap()),
+                Arc::new(RegexExtractor::new("priority", r"!!(\w+)", None).unwrap()),
+            ],
+            ..Default::default()
+        };
+        let content = "Fixes JIRA-1234, see JIRA-1234 and OPS-7 @ana\n%% @hidden %%\n";
+        let result = Extractor::extract(content, &options);
+
+        assert_eq!(result.custom_fields["tickets"], vec!["JIRA-1234", "OPS-7"]);
+        assert_eq!(result.custom_fields["mentions"], vec!["ana"]);
+        assert!(result.custom_fields["priority"].is_empty());
+    }
+
+    #[test]
+    fn test_extract_body_and_plain_text() {
+        let content =
+            "---\ntitle: Doc\n---\n# Heading\n\nSee **bold** and [[Other|alias]] %%note%%\n";
+        let result = Extractor::extract(content, &ExtractOptions::default());
+        assert_eq!(result.full_content, content);
+        assert!(!result.body.contains("title: Doc"));
+        assert!(result.body.contains("See **bold**"));
+        assert_eq!(result.plain_text, "Heading\n\nSee bold and alias");
+    }
+
+    #[test]
+    fn test_extract_code_blocks() {
+        let content = r#"---
+title: Snippets
+---
+
+```SQL title="count"
+SELECT count(*) FROM documents;
+```
+
+~~~~
+plain
+~~~
+still inside
+~~~~
+
+```mermaid
+graph TD
+"#;
+        let code_blocks = Extractor::extract(content, &ExtractOptions::default()).code_blocks;
+        assert_eq!(code_blocks.len(), 3);
+
+        assert_eq!(
+            code_blocks[0],
+            CodeBlock {
+                language: Some("sql".to_string()),
+                info: "SQL title=\"count\"".to_string(),
+                text: "SELECT count(*) FROM documents;".to_string(),
+                start_line: 5,
+                end_line: 7,
+            }
+        );
+
+        assert_eq!(code_blocks[1].language, None);
+        assert_eq!(code_blocks[1].text, "plain\n~~~\nstill inside");
+        assert_eq!(
+            (code_blocks[1].start_line, code_blocks[1].end_line),
+            (9, 13)
+        );
+
+        assert_eq!(code_blocks[2].language.as_deref(), Some("mermaid"));
+        assert_eq!(code_blocks[2].text, "graph TD");
+        assert_eq!(code_blocks[2].end_line, 16);
+    }
+
+    #[test]
+    fn test_extract_callouts() {
+        let content = r#"---
+title: Decisions
+---
+
+> [!question]+ Which database?
+> DuckDB or SQLite.
+> > [!note] Nested
+> > Inner text
+> Back to outer
+
+> [!WARNING]- Risk
+> Data loss
+
+> Plain quote
+"#;
+        let callouts = Extractor::extract(content, &ExtractOptions::default()).callouts;
+        assert_eq!(callouts.len(), 3);
+
+        assert_eq!(
+            callouts[0],
+            Callout {
+                kind: "question".to_string(),
+                title: Some("Which database?".to_string()),
+                fold: Some("open".to_string()),
+                body: "DuckDB or SQLite.\n> [!note] Nested\n> Inner text\nBack to outer"
+                    .to_string(),
+                depth: 1,
+                parent: None,
+                start_line: 5,
+                end_line: 9,
+            }
+        );
+
+        let nested = &callouts[1];
+        assert_eq!(nested.kind, "note");
+        assert_eq!(nested.fold, None);
+        assert_eq!((nested.depth, nested.parent), (2, Some(0)));
+        assert_eq!(nested.body, "Inner text");
+        assert_eq!((nested.start_line, nested.end_line), (7, 8));
+
+        let warning = &callouts[2];
+        assert_eq!(warning.kind, "warning");
+        assert_eq!(warning.title.as_deref(), Some("Risk"));
+        assert_eq!(warning.fold.as_deref(), Some("closed"));
+        assert_eq!(warning.body, "Data loss");
     }
 
     #[test]
@@ -234,8 +1759,8 @@ tags: [test]
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
-        let author = extracted.frontmatter.get("author").unwrap();
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        let author = extracted.properties.get("author").unwrap();
         assert_eq!(author.get("name").unwrap().as_str().unwrap(), "John Doe");
     }
 
@@ -248,33 +1773,22 @@ active: true
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
         assert_eq!(
-            extracted
-                .frontmatter
-                .get("count")
-                .unwrap()
-                .as_i64()
-                .unwrap(),
+            extracted.properties.get("count").unwrap().as_i64().unwrap(),
             42
         );
         assert_eq!(
-            extracted
-                .frontmatter
-                .get("price")
-                .unwrap()
-                .as_f64()
-                .unwrap(),
+            extracted.properties.get("price").unwrap().as_f64().unwrap(),
             19.99
         );
-        assert_eq!(
+        assert!(
             extracted
-                .frontmatter
+                .properties
                 .get("active")
                 .unwrap()
                 .as_bool()
-                .unwrap(),
-            true
+                .unwrap()
         );
     }
 
@@ -286,9 +1800,9 @@ modified: 2024-06-20T10:30:00Z
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
-        assert!(extracted.frontmatter.get("created").is_some());
-        assert!(extracted.frontmatter.get("modified").is_some());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(extracted.properties.get("created").is_some());
+        assert!(extracted.properties.get("modified").is_some());
     }
 
     #[test]
@@ -301,8 +1815,8 @@ categories:
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
-        let tags = extracted.frontmatter.get("tags").unwrap();
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        let tags = extracted.properties.get("tags").unwrap();
         assert!(tags.is_array());
         assert_eq!(tags.as_array().unwrap().len(), 3);
     }
@@ -315,8 +1829,8 @@ description: null
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
-        assert!(extracted.frontmatter.get("description").unwrap().is_null());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(extracted.properties.get("description").unwrap().is_null());
     }
 
     #[test]
@@ -328,9 +1842,9 @@ regex: ^\d{3}-\d{4}$
 ---
 
 Content"#;
-        let extracted = Extractor::extract(content);
-        assert!(extracted.frontmatter.get("title").is_some());
-        assert!(extracted.frontmatter.get("url").is_some());
-        assert!(extracted.frontmatter.get("regex").is_some());
+        let extracted = Extractor::extract(content, &ExtractOptions::default());
+        assert!(extracted.properties.get("title").is_some());
+        assert!(extracted.properties.get("url").is_some());
+        assert!(extracted.properties.get("regex").is_some());
     }
 }
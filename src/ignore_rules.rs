@@ -0,0 +1,316 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Decides which files and folders under a vault are left out of the index.
+///
+/// A path is skipped when it, or a folder it is in:
+/// - starts with a dot, like `.obsidian/`, `.trash/` or `.git/`,
+/// - is a `node_modules` folder,
+/// - matches a `.gitignore` or `.mdbignore` at the vault root or in a
+///   folder on the way to it,
+/// - matches Obsidian's "Excluded files" (`userIgnoreFilters` in
+///   `.obsidian/app.json`),
+/// - matches an `--exclude` glob.
+///
+/// When `--include` globs are given, only files matching one of them are
+/// indexed. The globs are relative to the indexed folder, the rest to the
+/// vault root.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    root: PathBuf,
+    base: PathBuf,
+    ignore_files: Gitignore,
+    obsidian: Vec<ObsidianFilter>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+/// An entry of Obsidian's excluded files: a path prefix, or a regex when
+/// written as `/pattern/`.
+#[derive(Debug, Clone)]
+enum ObsidianFilter {
+    Prefix(String),
+    Regex(Regex),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObsidianApp {
+    #[serde(default)]
+    user_ignore_filters: Vec<String>,
+}
+
+impl IgnoreRules {
+    /// Gitignore-style patterns for mdb only, read from the vault root.
+    pub const IGNORE_FILE: &'static str = ".mdbignore";
+    /// Gitignore-style files read in every folder.
+    pub const IGNORE_FILES: [&'static str; 2] = [".gitignore", Self::IGNORE_FILE];
+    const OBSIDIAN_APP: &'static str = ".obsidian/app.json";
+
+    /// Loads the rules of the vault at `root` for indexing `base`, the root
+    /// or a folder in it; `include` and `exclude` are globs relative to
+    /// `base`.
+    pub fn load(
+        root: &Path,
+        base: &Path,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = GitignoreBuilder::new(root);
+        for name in Self::IGNORE_FILES {
+            let path = root.join(name);
+            if path.is_file()
+                && let Some(err) = builder.add(&path)
+            {
+                return Err(format!("{}: {}", path.display(), err).into());
+            }
+        }
+        builder.add_line(None, "node_modules/")?;
+
+        let app_path = root.join(Self::OBSIDIAN_APP);
+        let obsidian = if app_path.is_file() {
+            let app: ObsidianApp = serde_json::from_str(&fs::read_to_string(&app_path)?)
+                .map_err(|e| format!("{}: {}", app_path.display(), e))?;
+            app.user_ignore_filters
+                .iter()
+                .map(|filter| ObsidianFilter::parse(filter))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{}: {}", app_path.display(), e))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(IgnoreRules {
+            root: root.to_path_buf(),
+            base: base.to_path_buf(),
+            ignore_files: builder.build()?,
+            obsidian,
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(glob_set(include)?)
+            },
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    /// Whether `path`, the root or a file or folder under it, is left out.
+    /// Paths outside the root never are.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative.as_os_str().is_empty() {
+            return false;
+        }
+        if relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+        {
+            return true;
+        }
+        if self
+            .ignore_files
+            .matched_path_or_any_parents(relative, is_dir)
+            .is_ignore()
+        {
+            return true;
+        }
+
+        // Folders above the indexed one, which a walk from the root passes
+        // through, are not matched against the globs.
+        if let Ok(from_base) = path.strip_prefix(&self.base)
+            && !from_base.as_os_str().is_empty()
+        {
+            let from_base = slash_path(from_base);
+            if self.exclude.is_match(&from_base) {
+                return true;
+            }
+            if !is_dir
+                && self
+                    .include
+                    .as_ref()
+                    .is_some_and(|include| !include.is_match(&from_base))
+            {
+                return true;
+            }
+        }
+
+        // Obsidian marks folders with a trailing `/`.
+        let mut relative = slash_path(relative);
+        if is_dir {
+            relative.push('/');
+        }
+        self.obsidian.iter().any(|filter| filter.matches(&relative))
+    }
+
+    /// Like `is_ignored`, but also true when a folder `path` is in is
+    /// ignored or when an ignore file in a folder below the root matches,
+    /// for paths that do not come from walking the root.
+    pub fn is_ignored_with_parents(&self, path: &Path, is_dir: bool) -> bool {
+        let mut parents = path
+            .ancestors()
+            .skip(1)
+            .take_while(|parent| parent.starts_with(&self.root));
+        parents.any(|parent| {
+            self.is_ignored(parent, true)
+                || (parent != self.root && Self::nested_ignores(parent, path, is_dir))
+        }) || self.is_ignored(path, is_dir)
+    }
+
+    /// Whether the ignore files in `folder` match `path`, which is in it.
+    fn nested_ignores(folder: &Path, path: &Path, is_dir: bool) -> bool {
+        let mut builder = GitignoreBuilder::new(folder);
+        for name in Self::IGNORE_FILES {
+            let file = folder.join(name);
+            if file.is_file() {
+                builder.add(&file);
+            }
+        }
+        builder
+            .build()
+            .is_ok_and(|ignore| ignore.matched_path_or_any_parents(path, is_dir).is_ignore())
+    }
+
+    /// Whether `path` is one of the files the rules are read from: an ignore
+    /// file in any folder, or Obsidian's settings.
+    pub fn is_rules_file(&self, path: &Path) -> bool {
+        path == self.root.join(Self::OBSIDIAN_APP)
+            || (path.starts_with(&self.root)
+                && path
+                    .file_name()
+                    .is_some_and(|name| Self::IGNORE_FILES.iter().any(|file| name == *file)))
+    }
+}
+
+impl ObsidianFilter {
+    fn parse(filter: &str) -> Result<Self, regex::Error> {
+        match filter
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            Some(pattern) if !pattern.is_empty() => Ok(ObsidianFilter::Regex(Regex::new(pattern)?)),
+            _ => Ok(ObsidianFilter::Prefix(filter.to_string())),
+        }
+    }
+
+    fn matches(&self, relative: &str) -> bool {
+        match self {
+            ObsidianFilter::Prefix(prefix) => relative.starts_with(prefix.as_str()),
+            ObsidianFilter::Regex(regex) => regex.is_match(relative),
+        }
+    }
+}
+
+/// `path` with its components joined by `/`, as globs and Obsidian
+/// filters are written.
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `*` stays within a folder, `**` crosses folders.
+fn glob_set(patterns: &[String]) -> Result<GlobSet, Box<dyn std::error::Error>> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static TEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    fn create_test_directory() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "test_ignore_{}_{}",
+            std::process::id(),
+            TEST_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".obsidian")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_ignore_files_and_hidden_folders() {
+        let dir = create_test_directory();
+        fs::write(dir.join(".gitignore"), "drafts/\n*.tmp.md\n").unwrap();
+        fs::write(dir.join(".mdbignore"), "archive/**\n!archive/keep.md\n").unwrap();
+        let rules = IgnoreRules::load(&dir, &dir, &[], &[]).unwrap();
+
+        assert!(!rules.is_ignored(&dir, true));
+        assert!(!rules.is_ignored(&dir.join("note.md"), false));
+        assert!(rules.is_ignored(&dir.join(".obsidian"), true));
+        assert!(rules.is_ignored(&dir.join(".trash/old.md"), false));
+        assert!(rules.is_ignored(&dir.join("node_modules"), true));
+        assert!(rules.is_ignored(&dir.join("drafts"), true));
+        assert!(rules.is_ignored(&dir.join("sub/drafts/idea.md"), false));
+        assert!(rules.is_ignored(&dir.join("scratch.tmp.md"), false));
+        assert!(rules.is_ignored(&dir.join("archive/2020/old.md"), false));
+        assert!(!rules.is_ignored(&dir.join("archive/keep.md"), false));
+        assert!(rules.is_rules_file(&dir.join(".mdbignore")));
+        assert!(rules.is_rules_file(&dir.join("sub/.gitignore")));
+        assert!(!rules.is_rules_file(&dir.join("note.md")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_obsidian_excluded_files() {
+        let dir = create_test_directory();
+        fs::write(
+            dir.join(".obsidian/app.json"),
+            r#"{"userIgnoreFilters": ["Templates/", "/\\.excalidraw\\.md$/"], "vimMode": true}"#,
+        )
+        .unwrap();
+        let rules = IgnoreRules::load(&dir, &dir, &[], &[]).unwrap();
+
+        assert!(rules.is_ignored(&dir.join("Templates"), true));
+        assert!(rules.is_ignored(&dir.join("Templates/daily.md"), false));
+        assert!(!rules.is_ignored(&dir.join("Templates.md"), false));
+        assert!(rules.is_ignored(&dir.join("drawing.excalidraw.md"), false));
+
+        fs::write(
+            dir.join(".obsidian/app.json"),
+            r#"{"userIgnoreFilters": ["/(unclosed/"]}"#,
+        )
+        .unwrap();
+        assert!(IgnoreRules::load(&dir, &dir, &[], &[]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_and_exclude_globs() {
+        let dir = create_test_directory();
+        let include = vec!["projects/**".to_string(), "*.canvas".to_string()];
+        let exclude = vec!["projects/old".to_string(), "**/*.draft.md".to_string()];
+        let rules = IgnoreRules::load(&dir, &dir, &include, &exclude).unwrap();
+
+        assert!(!rules.is_ignored(&dir.join("projects"), true));
+        assert!(!rules.is_ignored(&dir.join("projects/a/plan.md"), false));
+        assert!(!rules.is_ignored(&dir.join("board.canvas"), false));
+        assert!(rules.is_ignored(&dir.join("notes/board.canvas"), false));
+        assert!(rules.is_ignored(&dir.join("inbox.md"), false));
+        assert!(rules.is_ignored(&dir.join("projects/old"), true));
+        // Outside a walk, the excluded folder has to be looked up.
+        assert!(!rules.is_ignored(&dir.join("projects/old/plan.md"), false));
+        assert!(rules.is_ignored_with_parents(&dir.join("projects/old/plan.md"), false));
+        assert!(rules.is_ignored(&dir.join("projects/a/plan.draft.md"), false));
+
+        assert!(IgnoreRules::load(&dir, &dir, &["[".to_string()], &[]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
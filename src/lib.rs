@@ -1,4 +1,12 @@
+pub mod attachments;
+pub mod canvas;
+pub mod config;
+pub mod dates;
 pub mod db;
 pub mod extractor;
+pub mod ignore_rules;
 pub mod query;
 pub mod scanner;
+pub mod tags;
+pub mod vault;
+pub mod watcher;
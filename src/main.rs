@@ -1,7 +1,15 @@
+mod attachments;
+mod canvas;
+mod config;
+mod dates;
 mod db;
 mod extractor;
+mod ignore_rules;
 mod query;
 mod scanner;
+mod tags;
+mod vault;
+mod watcher;
 
 use clap::{Parser, Subcommand, ValueEnum};
 use std::env;
@@ -9,6 +17,7 @@ use std::path::PathBuf;
 use std::sync::Mutex;
 
 use crate::db::Database;
+use crate::vault::Vault;
 
 const ENV_DATABASE: &str = "MDB_DATABASE";
 const ENV_BASE_DIR: &str = "MDB_BASE_DIR";
@@ -20,6 +29,21 @@ enum OutputFormat {
     List,
 }
 
+#[derive(Clone, ValueEnum, Debug, PartialEq)]
+enum IndexOutput {
+    Text,
+    Json,
+}
+
+#[derive(Clone, ValueEnum, Debug, PartialEq)]
+enum QuerySource {
+    Documents,
+    CodeBlocks,
+    CanvasNodes,
+    CanvasEdges,
+    Attachments,
+}
+
 #[derive(Parser)]
 #[command(name = "mdb")]
 #[command(version = "0.1.0")]
@@ -47,6 +71,20 @@ struct Cli {
         help = "Directory to index (default: .)"
     )]
     base_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Print absolute paths instead of paths relative to the vault root"
+    )]
+    absolute: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Vault to index into, or to limit results to (default: all vaults)"
+    )]
+    vault: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -57,6 +95,66 @@ enum Commands {
 
         #[arg(short, long)]
         verbose: bool,
+
+        #[arg(long, help = "Store the text of %%comments%% in the comments column")]
+        index_comments: bool,
+
+        #[arg(long, help = "Do not store raw file content, only body and plain_text")]
+        no_content: bool,
+
+        #[arg(long, help = "Show what would be indexed and removed without writing")]
+        dry_run: bool,
+
+        #[arg(
+            short,
+            long,
+            help = "Threads walking folders, and workers reading and extracting files (default: one per core)"
+        )]
+        jobs: Option<usize>,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            help = "Only index files matching this glob, relative to the base directory (repeatable)"
+        )]
+        include: Vec<String>,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            help = "Skip files and folders matching this glob (repeatable)"
+        )]
+        exclude: Vec<String>,
+
+        #[arg(
+            long,
+            conflicts_with = "dry_run",
+            help = "Keep indexing changes as files are saved, moved and deleted"
+        )]
+        watch: bool,
+
+        #[arg(
+            long,
+            requires = "vault",
+            help = "Move the vault named by --vault to the base directory, even though its old root still exists"
+        )]
+        move_vault: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "watch",
+            help = "Exit with an error when any file could not be indexed"
+        )]
+        strict: bool,
+
+        #[arg(
+            short,
+            long,
+            default_value = "text",
+            conflicts_with_all = ["verbose", "watch"],
+            help = "Print the summary as text, or as a JSON report with counts, durations and failures"
+        )]
+        output: IndexOutput,
     },
     Query {
         #[arg(short, long)]
@@ -74,6 +172,39 @@ enum Commands {
 
         #[arg(short, long, default_value_t = 1000)]
         limit: usize,
+
+        #[arg(
+            long = "from",
+            default_value = "documents",
+            help = "Rows to query; code-blocks, canvas-nodes, canvas-edges and attachments add code.*, node.*, edge.* and attachment.* fields"
+        )]
+        source: QuerySource,
+    },
+    Tags {
+        #[arg(long, help = "Show nested tags as a tree with per-level counts")]
+        tree: bool,
+    },
+    Blocks {
+        #[arg(long, help = "List block links that point to a missing block")]
+        broken: bool,
+    },
+    Callouts {
+        #[arg(
+            short = 't',
+            long = "type",
+            help = "Only callouts of this type, e.g. question"
+        )]
+        kind: Option<String>,
+
+        #[arg(long, value_parser = ["open", "closed"], help = "Only foldable callouts in this state")]
+        fold: Option<String>,
+    },
+    Attachments {
+        #[arg(long, help = "Only attachments that no note links to or embeds")]
+        unused: bool,
+
+        #[arg(long, help = "List embeds that point to a missing file")]
+        broken: bool,
     },
 }
 
@@ -89,24 +220,117 @@ fn get_base_dir() -> PathBuf {
         .unwrap_or_else(|_| PathBuf::from("."))
 }
 
+/// The vaults results come from, when `--vault` or `--absolute` needs them:
+/// the one named, which must exist, or all of them. None otherwise, so
+/// commands on an empty database still just find nothing.
+fn shown_vaults(
+    db: &Database,
+    name: Option<&str>,
+    absolute: bool,
+) -> Result<Vec<Vault>, Box<dyn std::error::Error>> {
+    if name.is_none() && !absolute {
+        return Ok(Vec::new());
+    }
+    let vaults = db.get_vaults()?;
+    if vaults.is_empty() {
+        return Err("Nothing is indexed yet; run `mdb index` first".into());
+    }
+    match name {
+        Some(name) => {
+            let vault = vaults
+                .into_iter()
+                .find(|vault| vault.name == name)
+                .ok_or_else(|| format!("No vault named '{}'", name))?;
+            Ok(vec![vault])
+        }
+        None => Ok(vaults),
+    }
+}
+
+/// A stored path as printed: relative to the root of its vault, or, with
+/// `--absolute`, from the root of that vault among `vaults`.
+fn show_path(vaults: Option<&[Vault]>, vault: &str, path: &str) -> String {
+    match vaults.and_then(|vaults| vaults.iter().find(|v| v.name == vault)) {
+        Some(vault) => vault.absolute(path).to_string_lossy().to_string(),
+        None => path.to_string(),
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
     let db_path = cli.database.unwrap_or_else(get_database_path);
 
-    let db = Mutex::new(Database::new(&db_path)?);
+    let db = Database::new(&db_path)?;
+    if db.rebuilt() {
+        // On stderr, to keep query output clean.
+        eprintln!(
+            "{} was made by an older version of mdb and has been emptied; run `mdb index` to fill it again",
+            db_path.display()
+        );
+    }
+    let db = Mutex::new(db);
 
     match cli.command {
-        Commands::Index { force, verbose } => {
+        Commands::Index {
+            force,
+            verbose,
+            index_comments,
+            no_content,
+            dry_run,
+            jobs,
+            include,
+            exclude,
+            watch,
+            move_vault,
+            strict,
+            output,
+        } => {
             let base = cli.base_dir.unwrap_or_else(get_base_dir);
             let db = db.lock().unwrap();
-            scanner::index_directory(&base, &db, force, verbose)?;
+            let index = scanner::IndexOptions {
+                force,
+                verbose,
+                dry_run,
+                jobs: jobs.unwrap_or_else(scanner::default_jobs),
+                include,
+                exclude,
+                // Per-file lines would break it up.
+                progress: !verbose,
+                vault: cli.vault,
+                move_vault,
+            };
+            // Like the ignore files, the config of a vault is read from its
+            // root, whichever folder of it is indexed.
+            let vault = scanner::open_vault(&db, &base, &index)?;
+            let config = config::Config::load(&vault.root)?;
+            let options = extractor::ExtractOptions {
+                index_comments,
+                extractors: config.field_extractors()?,
+                periodic_formats: config.dates,
+                skip_content: no_content,
+            };
+            if watch {
+                watcher::watch_directory(&base, &db, &index, &options)?;
+            } else {
+                let report = scanner::index_directory(&base, &db, &index, &options)?;
+                match output {
+                    IndexOutput::Text => scanner::print_report(&db, &report, &index)?,
+                    IndexOutput::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                }
+                if strict && !report.errors.is_empty() {
+                    return Err(
+                        format!("{} files could not be indexed", report.errors.len()).into(),
+                    );
+                }
+            }
         }
         Commands::Query {
             query,
             format,
             limit,
             fields,
+            source,
         } => {
             let field_names: Vec<String> =
                 fields.split(',').map(|s| s.trim().to_string()).collect();
@@ -115,11 +339,120 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 OutputFormat::Json => "json",
                 OutputFormat::List => "list",
             };
-            let compiled = query::build_sql(&query, &fields).map_err(|e| e.to_string())?;
             let db = db.lock().unwrap();
-            let results = db.query(&compiled, &fields, limit)?;
+            let vaults = shown_vaults(&db, cli.vault.as_deref(), cli.absolute)?;
+            let query = match &cli.vault {
+                Some(vault) => query::within_vault(&query, vault),
+                None => query,
+            };
+            let compiled = match source {
+                QuerySource::Documents => query::build_sql(&query, &fields),
+                QuerySource::CodeBlocks => {
+                    query::build_sql_from(query::RowSource::CodeBlocks, &query, &fields)
+                }
+                QuerySource::CanvasNodes => {
+                    query::build_sql_from(query::RowSource::CanvasNodes, &query, &fields)
+                }
+                QuerySource::CanvasEdges => {
+                    query::build_sql_from(query::RowSource::CanvasEdges, &query, &fields)
+                }
+                QuerySource::Attachments => {
+                    query::build_sql_from(query::RowSource::Attachments, &query, &fields)
+                }
+            }
+            .map_err(|e| e.to_string())?;
+            let results = if cli.absolute {
+                // Path columns are found by name.
+                let mut table = db.query_table(&compiled, limit)?;
+                query::absolute_paths(&mut table, &vaults)?;
+                table.rows
+            } else {
+                db.query(&compiled, &fields, limit)?
+            };
             query::output_results(&results, format_str, &field_names)?;
         }
+        Commands::Tags { tree } => {
+            let db = db.lock().unwrap();
+            shown_vaults(&db, cli.vault.as_deref(), false)?;
+            let tag_map = db.get_all_tags(cli.vault.as_deref())?;
+            if tree {
+                for line in tags::TagTree::build(&tag_map).render() {
+                    println!("{}", line);
+                }
+            } else {
+                for (tag, count) in tags::tag_counts(&tag_map) {
+                    println!("{} ({})", tag, count);
+                }
+            }
+        }
+        Commands::Blocks { broken } => {
+            let db = db.lock().unwrap();
+            let vaults = shown_vaults(&db, cli.vault.as_deref(), cli.absolute)?;
+            let vaults = cli.absolute.then_some(vaults.as_slice());
+            let vault = cli.vault.as_deref();
+            if broken {
+                for (vault, path, link) in db.get_broken_block_refs(vault)? {
+                    println!("{} -> [[{}]]", show_path(vaults, &vault, &path), link);
+                }
+            } else {
+                for (vault, path, block) in db.get_blocks(vault)? {
+                    println!(
+                        "{}#^{} (lines {}-{}): {}",
+                        show_path(vaults, &vault, &path),
+                        block.id,
+                        block.start_line,
+                        block.end_line,
+                        block.text.replace('\n', " ")
+                    );
+                }
+            }
+        }
+        Commands::Callouts { kind, fold } => {
+            let db = db.lock().unwrap();
+            let vaults = shown_vaults(&db, cli.vault.as_deref(), cli.absolute)?;
+            let vaults = cli.absolute.then_some(vaults.as_slice());
+            let callouts =
+                db.get_callouts(kind.as_deref(), fold.as_deref(), cli.vault.as_deref())?;
+            for (vault, path, callout) in callouts {
+                let marker = match callout.fold.as_deref() {
+                    Some("open") => "+",
+                    Some("closed") => "-",
+                    _ => "",
+                };
+                println!(
+                    "{}:{} [!{}]{} {}",
+                    show_path(vaults, &vault, &path),
+                    callout.start_line,
+                    callout.kind,
+                    marker,
+                    callout.title.as_deref().unwrap_or("")
+                );
+            }
+        }
+        Commands::Attachments { unused, broken } => {
+            let db = db.lock().unwrap();
+            let vaults = shown_vaults(&db, cli.vault.as_deref(), cli.absolute)?;
+            let vaults = cli.absolute.then_some(vaults.as_slice());
+            let vault = cli.vault.as_deref();
+            if broken {
+                for (vault, path, embed) in db.get_broken_embeds(vault)? {
+                    println!("{} -> ![[{}]]", show_path(vaults, &vault, &path), embed);
+                }
+            } else {
+                for (vault, path, attachment) in db.get_attachments(unused, vault)? {
+                    let dimensions = match (attachment.width, attachment.height) {
+                        (Some(width), Some(height)) => format!(" {}x{}", width, height),
+                        _ => String::new(),
+                    };
+                    println!(
+                        "{} ({}{})",
+                        show_path(vaults, &vault, &path),
+                        attachment.mime,
+                        dimensions
+                    );
+                }
+            }
+        }
     }
 
     Ok(())
@@ -166,6 +499,180 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_index_options() {
+        let cli = Cli::parse_from([
+            "mdb",
+            "index",
+            "--index-comments",
+            "--no-content",
+            "--dry-run",
+            "--jobs",
+            "4",
+            "--include",
+            "projects/**",
+            "--exclude",
+            "archive",
+            "--exclude",
+            "*.draft.md",
+        ]);
+        if let Commands::Index {
+            index_comments,
+            no_content,
+            dry_run,
+            jobs,
+            include,
+            exclude,
+            ..
+        } = cli.command
+        {
+            assert!(index_comments);
+            assert!(no_content);
+            assert!(dry_run);
+            assert_eq!(jobs, Some(4));
+            assert_eq!(include, vec!["projects/**"]);
+            assert_eq!(exclude, vec!["archive", "*.draft.md"]);
+        } else {
+            panic!("Expected Index command");
+        }
+    }
+
+    #[test]
+    fn test_index_watch_option() {
+        let cli = Cli::parse_from(["mdb", "index", "--watch"]);
+        if let Commands::Index { watch, .. } = cli.command {
+            assert!(watch);
+        } else {
+            panic!("Expected Index command");
+        }
+
+        assert!(Cli::try_parse_from(["mdb", "index", "--watch", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn test_index_strict_option() {
+        let cli = Cli::parse_from(["mdb", "index", "--strict"]);
+        if let Commands::Index { strict, .. } = cli.command {
+            assert!(strict);
+        } else {
+            panic!("Expected Index command");
+        }
+
+        assert!(Cli::try_parse_from(["mdb", "index", "--strict", "--watch"]).is_err());
+    }
+
+    #[test]
+    fn test_index_output_option() {
+        let cli = Cli::parse_from(["mdb", "index"]);
+        if let Commands::Index { output, .. } = cli.command {
+            assert_eq!(output, IndexOutput::Text);
+        } else {
+            panic!("Expected Index command");
+        }
+
+        let cli = Cli::parse_from(["mdb", "index", "--output", "json", "--dry-run"]);
+        if let Commands::Index { output, .. } = cli.command {
+            assert_eq!(output, IndexOutput::Json);
+        } else {
+            panic!("Expected Index command");
+        }
+
+        assert!(Cli::try_parse_from(["mdb", "index", "-o", "json", "-v"]).is_err());
+        assert!(Cli::try_parse_from(["mdb", "index", "-o", "json", "--watch"]).is_err());
+    }
+
+    #[test]
+    fn test_absolute_option() {
+        let cli = Cli::parse_from(["mdb", "query", "-q", "SELECT path"]);
+        assert!(!cli.absolute);
+
+        let cli = Cli::parse_from(["mdb", "query", "-q", "SELECT path", "--absolute"]);
+        assert!(cli.absolute);
+        let cli = Cli::parse_from(["mdb", "--absolute", "attachments"]);
+        assert!(cli.absolute);
+    }
+
+    #[test]
+    fn test_vault_option() {
+        let cli = Cli::parse_from(["mdb", "index", "--vault", "team"]);
+        assert_eq!(cli.vault.as_deref(), Some("team"));
+
+        let cli = Cli::parse_from(["mdb", "--vault", "team", "query", "-q", "has(tags, 'x')"]);
+        assert_eq!(cli.vault.as_deref(), Some("team"));
+        let cli = Cli::parse_from(["mdb", "tags"]);
+        assert_eq!(cli.vault, None);
+    }
+
+    #[test]
+    fn test_tags_tree_option() {
+        let cli = Cli::parse_from(["mdb", "tags", "--tree"]);
+        if let Commands::Tags { tree } = cli.command {
+            assert!(tree);
+        } else {
+            panic!("Expected Tags command");
+        }
+    }
+
+    #[test]
+    fn test_callouts_filter_options() {
+        let cli = Cli::parse_from(["mdb", "callouts", "--type", "question", "--fold", "open"]);
+        if let Commands::Callouts { kind, fold } = cli.command {
+            assert_eq!(kind.as_deref(), Some("question"));
+            assert_eq!(fold.as_deref(), Some("open"));
+        } else {
+            panic!("Expected Callouts command");
+        }
+    }
+
+    #[test]
+    fn test_attachments_options() {
+        let cli = Cli::parse_from(["mdb", "attachments", "--unused"]);
+        if let Commands::Attachments { unused, broken } = cli.command {
+            assert!(unused);
+            assert!(!broken);
+        } else {
+            panic!("Expected Attachments command");
+        }
+    }
+
+    #[test]
+    fn test_query_from_option() {
+        let cli = Cli::parse_from(["mdb", "query", "-q", "code.language == 'sql'"]);
+        if let Commands::Query { source, .. } = cli.command {
+            assert_eq!(source, QuerySource::Documents);
+        } else {
+            panic!("Expected Query command");
+        }
+
+        let cli = Cli::parse_from([
+            "mdb",
+            "query",
+            "-q",
+            "code.language == 'sql'",
+            "--from",
+            "code-blocks",
+        ]);
+        if let Commands::Query { source, .. } = cli.command {
+            assert_eq!(source, QuerySource::CodeBlocks);
+        } else {
+            panic!("Expected Query command");
+        }
+
+        let cli = Cli::parse_from([
+            "mdb",
+            "query",
+            "-q",
+            "node.type == 'file'",
+            "--from",
+            "canvas-nodes",
+        ]);
+        if let Commands::Query { source, .. } = cli.command {
+            assert_eq!(source, QuerySource::CanvasNodes);
+        } else {
+            panic!("Expected Query command");
+        }
+    }
+
     #[test]
     fn test_output_format_option() {
         let cli = Cli::parse_from(["mdb", "query", "-q", "file.name == 'test'", "-o", "json"]);
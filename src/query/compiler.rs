@@ -1,6 +1,7 @@
 use super::parser::AstNode;
 
-const FILE_FIELDS: &[&str] = &[
+pub const FILE_FIELDS: &[&str] = &[
+    "vault",
     "path",
     "folder",
     "name",
@@ -10,28 +11,132 @@ const FILE_FIELDS: &[&str] = &[
     "mtime",
     "content",
     "tags",
+    "tag_sources",
+    "aliases",
     "links",
     "backlinks",
     "embeds",
+    "property_sources",
+    "words",
+    "chars",
+    "lines",
+    "reading_time",
+    "title",
+    "excerpt",
+    "frontmatter_format",
+    "errors",
+    "callout_types",
+    "code_languages",
+    "highlights",
+    "footnotes",
+    "comments",
+    "date",
+    "date_source",
+    "body",
+    "plain_text",
+    "hash",
 ];
 
+/// Columns that can also be named without `file.`, as they could before any
+/// column was added. Every later column is reached only as `file.<name>`, so
+/// a bare `date`, `title` or `body` stays the note property of that name.
+const SHORTHAND_FIELDS: &[&str] = &[
+    "path",
+    "folder",
+    "name",
+    "ext",
+    "size",
+    "ctime",
+    "mtime",
+    "content",
+    "tags",
+    "links",
+    "backlinks",
+    "embeds",
+];
+
+const CODE_BLOCK_FIELDS: &[&str] = &["language", "info", "text", "start_line", "end_line"];
+
+const ATTACHMENT_FIELDS: &[&str] = &["mime", "width", "height"];
+
+/// `node.*` fields and the `canvas_nodes` columns they read.
+const CANVAS_NODE_FIELDS: &[(&str, &str)] = &[
+    ("id", "node_id"),
+    ("type", "node_type"),
+    ("text", "text"),
+    ("file", "file"),
+    ("subpath", "subpath"),
+    ("url", "url"),
+    ("label", "label"),
+    ("x", "x"),
+    ("y", "y"),
+    ("width", "width"),
+    ("height", "height"),
+];
+
+/// `edge.*` fields and the `canvas_edges` columns they read.
+const CANVAS_EDGE_FIELDS: &[(&str, &str)] = &[
+    ("id", "edge_id"),
+    ("from", "from_node"),
+    ("to", "to_node"),
+    ("label", "label"),
+];
+
+/// Table a query selects rows from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowSource {
+    Documents,
+    /// One row per fenced code block, joined with its document so `file.*`
+    /// and note properties remain available.
+    CodeBlocks,
+    /// One row per card of a `.canvas` file, with `node.*` fields.
+    CanvasNodes,
+    /// One row per canvas edge, with `edge.*` fields.
+    CanvasEdges,
+    /// One row per image, PDF or other attachment, with `attachment.*`
+    /// fields.
+    Attachments,
+}
+
 pub fn resolve_field(field: &str) -> String {
     if field.contains('.') {
         let parts: Vec<&str> = field.split('.').collect();
         if parts.len() == 2 {
             let prefix = parts[0];
             let name = parts[1];
-            if prefix == "file" && FILE_FIELDS.contains(&name) {
+            // Besides the built-in columns, `file.*` reaches the list columns
+            // added by custom extractors, which are only known at index time.
+            if prefix == "file" && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
                 return name.to_string();
             }
             if prefix == "note" {
                 return format!("json_extract_string(properties, '$.{}')", name);
             }
+            if prefix == "code" && CODE_BLOCK_FIELDS.contains(&name) {
+                return format!("code_blocks.{}", name);
+            }
+            if prefix == "attachment" && ATTACHMENT_FIELDS.contains(&name) {
+                return format!("attachments.{}", name);
+            }
+            // Every file's content hash is kept in `documents`.
+            if prefix == "attachment" && name == "hash" {
+                return "documents.hash".to_string();
+            }
+            let canvas_fields = match prefix {
+                "node" => Some(("canvas_nodes", CANVAS_NODE_FIELDS)),
+                "edge" => Some(("canvas_edges", CANVAS_EDGE_FIELDS)),
+                _ => None,
+            };
+            if let Some((table, fields)) = canvas_fields
+                && let Some((_, column)) = fields.iter().find(|(field, _)| *field == name)
+            {
+                return format!("{}.{}", table, column);
+            }
         }
         return field.to_string();
     }
 
-    if FILE_FIELDS.contains(&field) {
+    if SHORTHAND_FIELDS.contains(&field) {
         return field.to_string();
     }
 
@@ -63,10 +168,7 @@ pub fn compile(node: &AstNode) -> String {
                 format!("{} {} {}", left_sql, sql_op, right_sql)
             }
         }
-        AstNode::Field(name) => {
-            let resolved = resolve_field(name);
-            resolved
-        }
+        AstNode::Field(name) => resolve_field(name),
         AstNode::StringLiteral(val) => {
             format!("'{}'", val.replace('\'', "''"))
         }
@@ -78,6 +180,17 @@ pub fn compile(node: &AstNode) -> String {
                 let clean_value = value.trim_matches('\'');
                 return format!("'{}' = ANY({})", clean_value, field);
             }
+            if name == "has_tag" && args.len() == 2 {
+                // Nested tags: a parent tag also matches any of its descendants.
+                // Tags compare case-insensitively, as in Obsidian.
+                let field = compile(&args[0]);
+                let value = compile(&args[1]);
+                let clean_value = value.trim_matches('\'').trim_matches('/').to_lowercase();
+                return format!(
+                    "len(list_filter({}, t -> lower(t) = '{}' OR starts_with(lower(t), '{}/'))) > 0",
+                    field, clean_value, clean_value
+                );
+            }
             "1=1".to_string()
         }
         AstNode::Grouping(expr) => {
@@ -87,19 +200,36 @@ pub fn compile(node: &AstNode) -> String {
 }
 
 pub fn build_sql(query: &str, fields: &str) -> Result<String, String> {
+    build_sql_from(RowSource::Documents, query, fields)
+}
+
+pub fn build_sql_from(source: RowSource, query: &str, fields: &str) -> Result<String, String> {
     let parsed = super::parser::parse(query);
     let where_clause = compile(&parsed);
 
-    let select_fields: String = if fields == "*" {
-        "path, folder, name, ext, size, ctime, mtime, content, tags, links, backlinks, embeds, properties".to_string()
-    } else {
-        let resolved: Vec<String> = fields.split(',').map(|f| resolve_field(f.trim())).collect();
-        resolved.join(", ")
+    let select_fields: String = match (source, fields) {
+        (RowSource::Documents, "*") => "vault, path, folder, name, ext, size, ctime, mtime, content, tags, tag_sources, aliases, links, backlinks, embeds, properties, property_sources, words, chars, lines, reading_time, title, excerpt, frontmatter_format, errors, callout_types, code_languages, highlights, footnotes, comments, date, date_source, body, plain_text, hash".to_string(),
+        (RowSource::CodeBlocks, "*") => "path, code_blocks.language, code_blocks.info, code_blocks.start_line, code_blocks.end_line, code_blocks.text".to_string(),
+        (RowSource::CanvasNodes, "*") => "path, canvas_nodes.node_id, canvas_nodes.node_type, canvas_nodes.text, canvas_nodes.file, canvas_nodes.url, canvas_nodes.label".to_string(),
+        (RowSource::Attachments, "*") => "path, ext, size, attachments.mime, attachments.width, attachments.height, documents.hash".to_string(),
+        (RowSource::CanvasEdges, "*") => "path, canvas_edges.edge_id, canvas_edges.from_node, canvas_edges.to_node, canvas_edges.label".to_string(),
+        _ => {
+            let resolved: Vec<String> = fields.split(',').map(|f| resolve_field(f.trim())).collect();
+            resolved.join(", ")
+        }
+    };
+
+    let from = match source {
+        RowSource::Documents => "documents",
+        RowSource::CodeBlocks => "code_blocks JOIN documents USING (vault, path)",
+        RowSource::CanvasNodes => "canvas_nodes JOIN documents USING (vault, path)",
+        RowSource::CanvasEdges => "canvas_edges JOIN documents USING (vault, path)",
+        RowSource::Attachments => "attachments JOIN documents USING (vault, path)",
     };
 
     Ok(format!(
-        "SELECT {} FROM documents WHERE {}",
-        select_fields, where_clause
+        "SELECT {} FROM {} WHERE {}",
+        select_fields, from, where_clause
     ))
 }
 
@@ -111,6 +241,7 @@ mod tests {
     fn test_resolve_file_field() {
         assert_eq!(resolve_field("file.name"), "name");
         assert_eq!(resolve_field("file.size"), "size");
+        assert_eq!(resolve_field("file.vault"), "vault");
         assert_eq!(resolve_field("file.path"), "path");
         assert_eq!(resolve_field("file.folder"), "folder");
         assert_eq!(resolve_field("file.ext"), "ext");
@@ -118,9 +249,38 @@ mod tests {
         assert_eq!(resolve_field("file.mtime"), "mtime");
         assert_eq!(resolve_field("file.content"), "content");
         assert_eq!(resolve_field("file.tags"), "tags");
+        assert_eq!(resolve_field("file.tag_sources"), "tag_sources");
+        assert_eq!(resolve_field("file.aliases"), "aliases");
         assert_eq!(resolve_field("file.links"), "links");
         assert_eq!(resolve_field("file.backlinks"), "backlinks");
         assert_eq!(resolve_field("file.embeds"), "embeds");
+        assert_eq!(resolve_field("file.property_sources"), "property_sources");
+        assert_eq!(resolve_field("file.words"), "words");
+        assert_eq!(resolve_field("file.chars"), "chars");
+        assert_eq!(resolve_field("file.lines"), "lines");
+        assert_eq!(resolve_field("file.reading_time"), "reading_time");
+        assert_eq!(resolve_field("file.title"), "title");
+        assert_eq!(resolve_field("file.excerpt"), "excerpt");
+        assert_eq!(
+            resolve_field("file.frontmatter_format"),
+            "frontmatter_format"
+        );
+        assert_eq!(resolve_field("file.errors"), "errors");
+        assert_eq!(resolve_field("file.callout_types"), "callout_types");
+        assert_eq!(resolve_field("file.code_languages"), "code_languages");
+    }
+
+    #[test]
+    fn test_resolve_custom_extractor_field() {
+        assert_eq!(resolve_field("file.tickets"), "tickets");
+        assert_eq!(
+            resolve_field("tickets"),
+            "json_extract_string(properties, '$.tickets')"
+        );
+        assert_eq!(
+            build_sql("has(file.tickets, 'JIRA-1')", "path,file.tickets").unwrap(),
+            "SELECT path, tickets FROM documents WHERE 'JIRA-1' = ANY(tickets)"
+        );
     }
 
     #[test]
@@ -151,6 +311,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_stat_field() {
+        assert_eq!(resolve_field("file.title"), "title");
+        assert_eq!(
+            resolve_field("title"),
+            "json_extract_string(properties, '$.title')"
+        );
+        assert_eq!(
+            resolve_field("note.title"),
+            "json_extract_string(properties, '$.title')"
+        );
+        assert_eq!(
+            build_sql(
+                "file.words < 50 and words == 'many'",
+                "path,file.title,title"
+            )
+            .unwrap(),
+            "SELECT path, title, json_extract_string(properties, '$.title') FROM documents WHERE words < 50 AND json_extract_string(properties, '$.words') = 'many'"
+        );
+    }
+
+    #[test]
+    fn test_resolve_added_field_needs_prefix() {
+        for field in ["date", "body", "hash", "errors", "aliases", "vault"] {
+            assert_eq!(
+                resolve_field(field),
+                format!("json_extract_string(properties, '$.{}')", field)
+            );
+            assert_eq!(resolve_field(&format!("file.{}", field)), field);
+        }
+        assert_eq!(resolve_field("tags"), "tags");
+        assert_eq!(resolve_field("mtime"), "mtime");
+    }
+
     #[test]
     fn test_resolve_note_custom_property() {
         assert_eq!(
@@ -223,6 +417,16 @@ mod tests {
         assert_eq!(sql, "'important' = ANY(tags)");
     }
 
+    #[test]
+    fn test_compile_function_has_tag() {
+        let ast = super::super::parser::parse("has_tag(tags, 'projects')");
+        let sql = compile(&ast);
+        assert_eq!(
+            sql,
+            "len(list_filter(tags, t -> lower(t) = 'projects' OR starts_with(lower(t), 'projects/'))) > 0"
+        );
+    }
+
     #[test]
     fn test_compile_complex_query() {
         let ast = super::super::parser::parse(
@@ -257,11 +461,70 @@ mod tests {
         let result = build_sql("file.name == 'test'", "*");
         assert!(result.is_ok());
         let sql = result.unwrap();
-        assert!(sql.contains("SELECT path, folder, name"));
+        assert!(sql.contains("SELECT vault, path, folder, name"));
         assert!(sql.contains("FROM documents"));
         assert!(sql.contains("name = 'test'"));
     }
 
+    #[test]
+    fn test_resolve_code_block_field() {
+        assert_eq!(resolve_field("code.language"), "code_blocks.language");
+        assert_eq!(resolve_field("code.text"), "code_blocks.text");
+        assert_eq!(
+            resolve_field("language"),
+            "json_extract_string(properties, '$.language')"
+        );
+    }
+
+    #[test]
+    fn test_build_sql_from_code_blocks() {
+        let sql = build_sql_from(
+            RowSource::CodeBlocks,
+            "code.language == 'sql'",
+            "file.path, code.start_line",
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            "SELECT path, code_blocks.start_line FROM code_blocks JOIN documents USING (vault, path) WHERE code_blocks.language = 'sql'"
+        );
+    }
+
+    #[test]
+    fn test_resolve_canvas_fields() {
+        assert_eq!(resolve_field("node.type"), "canvas_nodes.node_type");
+        assert_eq!(resolve_field("node.text"), "canvas_nodes.text");
+        assert_eq!(resolve_field("edge.from"), "canvas_edges.from_node");
+        assert_eq!(resolve_field("edge.label"), "canvas_edges.label");
+        assert_eq!(resolve_field("node.missing"), "node.missing");
+    }
+
+    #[test]
+    fn test_build_sql_from_attachments() {
+        assert_eq!(resolve_field("attachment.mime"), "attachments.mime");
+        assert_eq!(resolve_field("attachment.hash"), "documents.hash");
+        let sql =
+            build_sql_from(RowSource::Attachments, "attachment.width > 1000", "path").unwrap();
+        assert_eq!(
+            sql,
+            "SELECT path FROM attachments JOIN documents USING (vault, path) WHERE attachments.width > 1000"
+        );
+    }
+
+    #[test]
+    fn test_build_sql_from_canvas_nodes() {
+        let sql = build_sql_from(
+            RowSource::CanvasNodes,
+            "node.type == 'file'",
+            "path,node.file",
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            "SELECT path, canvas_nodes.file FROM canvas_nodes JOIN documents USING (vault, path) WHERE canvas_nodes.node_type = 'file'"
+        );
+    }
+
     #[test]
     fn test_build_sql_with_custom_fields() {
         let result = build_sql("file.name == 'test'", "path,name");
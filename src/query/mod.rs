@@ -2,7 +2,52 @@ pub mod compiler;
 pub mod parser;
 pub mod tokenizer;
 
-pub use compiler::build_sql;
+pub use compiler::{RowSource, build_sql, build_sql_from};
+
+use crate::db::QueryTable;
+use crate::vault::Vault;
+
+/// Narrows `query` to the files of one vault.
+pub fn within_vault(query: &str, vault: &str) -> String {
+    format!("file.vault == '{}' and ({})", vault, query)
+}
+
+/// Turns the paths in `table`, stored relative to the root of their vault,
+/// into absolute paths: `path` and `folder` columns and the `backlinks`
+/// list. The vault of a row is read from its `vault` column, which can only
+/// be left out when `vaults` holds a single vault.
+pub fn absolute_paths(table: &mut QueryTable, vaults: &[Vault]) -> Result<(), String> {
+    let vault_column = table.columns.iter().position(|column| column == "vault");
+    for row in &mut table.rows {
+        let vault = match vault_column {
+            Some(i) => vaults.iter().find(|vault| vault.name == row[i]),
+            None if vaults.len() == 1 => vaults.first(),
+            None => {
+                return Err(
+                    "Add file.vault to the fields to print absolute paths from several vaults"
+                        .to_string(),
+                );
+            }
+        };
+        let Some(vault) = vault else {
+            continue;
+        };
+        let absolute = |path: &str| vault.absolute(path).to_string_lossy().to_string();
+        for (column, value) in table.columns.iter().zip(row.iter_mut()) {
+            match column.as_str() {
+                "path" | "folder" => *value = absolute(value),
+                "backlinks" => {
+                    if let Ok(paths) = serde_json::from_str::<Vec<String>>(value) {
+                        let paths: Vec<String> = paths.iter().map(|p| absolute(p)).collect();
+                        *value = serde_json::to_string(&paths).unwrap_or_default();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
 
 pub fn output_results(
     results: &[Vec<String>],
@@ -75,7 +120,7 @@ fn output_table(
         .map(|i| {
             field_names
                 .get(i)
-                .map(|s| s.clone())
+                .cloned()
                 .unwrap_or_else(|| format!("col{}", i))
         })
         .collect();
@@ -241,4 +286,53 @@ mod tests {
             assert!(result.is_ok(), "Failed for format: {}", format);
         }
     }
+
+    #[test]
+    fn test_absolute_paths() {
+        let vault = |name: &str| Vault {
+            name: name.to_string(),
+            root: std::path::PathBuf::from("/home/me").join(name),
+        };
+        let mut table = QueryTable {
+            columns: vec![
+                "path".to_string(),
+                "folder".to_string(),
+                "name".to_string(),
+                "backlinks".to_string(),
+            ],
+            rows: vec![vec![
+                "projects/plan.md".to_string(),
+                "projects".to_string(),
+                "plan".to_string(),
+                r#"["inbox.md"]"#.to_string(),
+            ]],
+        };
+        let mut several = table.clone();
+        absolute_paths(&mut table, &[vault("notes")]).unwrap();
+        assert_eq!(
+            table.rows[0],
+            vec![
+                "/home/me/notes/projects/plan.md".to_string(),
+                "/home/me/notes/projects".to_string(),
+                "plan".to_string(),
+                r#"["/home/me/notes/inbox.md"]"#.to_string(),
+            ]
+        );
+
+        // Across vaults, each row needs its vault.
+        let vaults = [vault("notes"), vault("team")];
+        assert!(absolute_paths(&mut several.clone(), &vaults).is_err());
+        several.columns.push("vault".to_string());
+        several.rows[0].push("team".to_string());
+        absolute_paths(&mut several, &vaults).unwrap();
+        assert_eq!(several.rows[0][0], "/home/me/team/projects/plan.md");
+    }
+
+    #[test]
+    fn test_within_vault() {
+        assert_eq!(
+            within_vault("has(tags, 'a') or has(tags, 'b')", "team"),
+            "file.vault == 'team' and (has(tags, 'a') or has(tags, 'b'))"
+        );
+    }
 }
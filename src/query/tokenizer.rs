@@ -1,4 +1,5 @@
 #[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Field(String),
     Operator(String),
@@ -98,7 +99,7 @@ impl Lexer {
         }
         let ident: String = self.input[start..self.pos].iter().collect();
 
-        if ident == "has" {
+        if ident == "has" || ident == "has_tag" {
             return Token::Function(ident);
         }
 
@@ -119,12 +120,7 @@ impl Lexer {
 
         if self.pos < self.input.len() {
             let next = self.input[self.pos];
-            if (ch == '=' && next == '=')
-                || (ch == '!' && next == '=')
-                || (ch == '>' && next == '=')
-                || (ch == '<' && next == '=')
-                || (ch == '=' && next == '~')
-            {
+            if (next == '=' && matches!(ch, '=' | '!' | '>' | '<')) || (ch == '=' && next == '~') {
                 self.pos += 1;
                 return Token::Operator(self.input[start..self.pos].iter().collect());
             }
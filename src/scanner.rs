@@ -1,105 +1,1141 @@
-use crate::db::{Database, Document};
-use crate::extractor::Extractor;
+use crate::attachments::{self, Attachment};
+use crate::canvas::Canvas;
+use crate::dates;
+use crate::db::{Database, Document, FileState, is_conflict, link_names_of};
+use crate::extractor::{ExtractError, ExtractOptions, ExtractedContent, Extractor};
+use crate::ignore_rules::IgnoreRules;
+use crate::vault::{self, Vault};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::time::UNIX_EPOCH;
-use walkdir::WalkDir;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How `index_directory` runs, as opposed to what it extracts.
+#[derive(Debug, Clone)]
+pub struct IndexOptions {
+    /// Re-index every file, even when its content is unchanged.
+    pub force: bool,
+    pub verbose: bool,
+    /// Report what would change without writing to the database.
+    pub dry_run: bool,
+    /// Threads walking folders, and workers reading and extracting files.
+    pub jobs: usize,
+    /// Globs relative to the indexed directory; when any are given, only
+    /// matching files are indexed.
+    pub include: Vec<String>,
+    /// Globs of files and folders to skip, on top of `IgnoreRules`.
+    pub exclude: Vec<String>,
+    /// Draw a progress bar on stderr while scanning, when it is a terminal.
+    pub progress: bool,
+    /// Name of the vault to index into. By default, the vault the directory
+    /// is in, or a new one named after the directory.
+    pub vault: Option<String>,
+    /// Let `vault` move to the indexed directory while its old root still
+    /// exists.
+    pub move_vault: bool,
+}
+
+impl Default for IndexOptions {
+    fn default() -> Self {
+        IndexOptions {
+            force: false,
+            verbose: false,
+            dry_run: false,
+            jobs: default_jobs(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            progress: false,
+            vault: None,
+            move_vault: false,
+        }
+    }
+}
+
+/// One worker per available core.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// What a worker found for one file.
+enum Scanned {
+    /// Same content as indexed; `mtime` is the file's current mtime.
+    Unchanged { path: String, mtime: i64 },
+    /// New or changed, with everything extracted from it.
+    Changed { doc: Box<Document>, is_new: bool },
+}
+
+/// What indexing was doing with a file when it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Listing the folder it is in.
+    Walk,
+    /// Reading the file or its metadata.
+    Read,
+    /// Storing it in the database.
+    Write,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Walk => "walk",
+            Stage::Read => "read",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// A file or folder that could not be indexed. The rest of the run goes on
+/// without it, and an indexed row for it is kept as it was.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileError {
+    pub path: String,
+    pub stage: Stage,
+    pub error: String,
+}
+
+impl FileError {
+    fn new(vault: &Vault, path: &Path, stage: Stage, error: impl fmt::Display) -> Self {
+        // Relative like indexed paths, so the row it protects is found.
+        let path = vault
+            .relative(path)
+            .unwrap_or_else(|| path.to_string_lossy().to_string());
+        FileError {
+            path,
+            stage,
+            error: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.path, self.stage.as_str(), self.error)
+    }
+}
+
+/// What an indexing run did, or in a dry run would have done.
+#[derive(Debug, Default, Serialize)]
+pub struct IndexReport {
+    /// Name of the vault indexed.
+    pub vault: String,
+    /// Files indexed for the first time.
+    pub added: usize,
+    /// Indexed files extracted again because their content changed, or all
+    /// of them with `force`.
+    pub updated: usize,
+    /// Indexed files with the same content, left as they are.
+    pub unchanged: usize,
+    /// Indexed files found under a new path.
+    pub renamed: usize,
+    /// Indexed files that are gone or now ignored.
+    pub deleted: usize,
+    /// Files that could not be indexed, listed in `errors`.
+    pub failed: usize,
+    pub durations: Durations,
+    /// Files that were skipped, in the order they failed.
+    pub errors: Vec<FileError>,
+}
+
+/// Time spent in each phase of a run, in milliseconds in JSON.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Durations {
+    /// Walking, reading and extracting files, with changed files written as
+    /// they come.
+    #[serde(rename = "scan_ms", serialize_with = "as_millis")]
+    pub scan: Duration,
+    /// Writing new files and moves, and removing deleted files.
+    #[serde(rename = "store_ms", serialize_with = "as_millis")]
+    pub store: Duration,
+    /// Recomputing backlinks.
+    #[serde(rename = "backlinks_ms", serialize_with = "as_millis")]
+    pub backlinks: Duration,
+    #[serde(rename = "total_ms", serialize_with = "as_millis")]
+    pub total: Duration,
+}
+
+fn as_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+impl IndexReport {
+    /// Files written, new or changed.
+    pub fn indexed(&self) -> usize {
+        self.added + self.updated
+    }
 
+    /// Whether the run changed the index.
+    pub fn has_changes(&self) -> bool {
+        self.indexed() + self.renamed + self.deleted > 0
+    }
+
+    /// Prints the failures, if any.
+    pub fn print_errors(&self) {
+        if !self.errors.is_empty() {
+            println!("Failed to index {} files", self.errors.len());
+            for error in &self.errors {
+                println!("  {}", error);
+            }
+        }
+    }
+
+    fn fail(&mut self, error: FileError) {
+        self.failed += 1;
+        self.errors.push(error);
+    }
+
+    /// Whether indexed `path` failed this run, itself or a folder it is in,
+    /// so its row must not be taken for a deleted file.
+    fn is_failed(&self, path: &str) -> bool {
+        self.errors
+            .iter()
+            .any(|error| Path::new(path).starts_with(&error.path))
+    }
+}
+
+/// Changed files written per transaction while the scan runs.
+const WRITE_BATCH: usize = 500;
+
+/// Indexes `dir` as a pipeline: `jobs` threads walk the tree, `jobs` workers
+/// read, hash and extract files, and the calling thread is the only one
+/// writing to the database.
+///
+/// A file that cannot be read or stored is skipped and listed in the
+/// returned report; only database and setup errors end the run. Nothing is
+/// printed but, with `verbose`, each file written.
 pub fn index_directory(
     dir: &Path,
     db: &Database,
-    force: bool,
-    verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut count = 0;
-    let mut all_docs: Vec<Document> = Vec::new();
+    index: &IndexOptions,
+    options: &ExtractOptions,
+) -> Result<IndexReport, Box<dyn std::error::Error>> {
+    let IndexOptions {
+        force,
+        verbose,
+        dry_run,
+        jobs,
+        ref include,
+        ref exclude,
+        progress,
+        vault: _,
+        move_vault: _,
+    } = *index;
+    let started = Instant::now();
+    // Changed files and the names their links point at, before and after
+    // the change, so the backlinks they affect can be recomputed.
+    let mut changed: HashSet<String> = HashSet::new();
+    let mut link_names: HashSet<String> = HashSet::new();
+    let mut batch: Vec<Document> = Vec::new();
+    let mut new_docs: Vec<Document> = Vec::new();
+    // Unchanged files with a new mtime, written a batch at a time too.
+    let mut touched: Vec<(String, i64)> = Vec::new();
+    // Every indexable file under `dir`, changed or not, so rows of files
+    // that are gone can be told apart.
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut report = IndexReport::default();
+
+    if !dry_run {
+        let custom_columns: Vec<&str> = options.extractors.iter().map(|e| e.name()).collect();
+        db.add_list_columns(&custom_columns)?;
+    }
 
-    for entry in WalkDir::new(dir)
-        .follow_links(true)
+    let dir = &dir.canonicalize()?;
+    let vault = open_vault(db, dir, index)?;
+    // Ignore files apply from the vault root, whichever folder is indexed.
+    let rules = IgnoreRules::load(&vault.root, dir, include, exclude)?;
+    report.vault = vault.name.clone();
+    // Loaded up front so workers can skip unchanged files without the
+    // database, which only the writer touches.
+    let indexed = db.get_file_states(&vault.name)?;
+    let links_before = db.get_all_links(&vault.name)?;
+    let keys_before = db.get_link_keys(&vault.name)?;
+    let jobs = jobs.max(1);
+    let (result_tx, result_rx) = mpsc::sync_channel::<Result<Scanned, FileError>>(jobs * 16);
+    // Its length grows as the walk finds files.
+    let progress = if progress {
+        ProgressBar::new(0).with_style(ProgressStyle::with_template(
+            "{spinner} [{elapsed}] {wide_bar} {pos}/{len} files",
+        )?)
+    } else {
+        ProgressBar::hidden()
+    };
+
+    std::thread::scope(|scope| -> Result<(), Box<dyn std::error::Error>> {
+        let (rules, vault) = (&rules, &vault);
+        // Shared by the workers alone, so once they stop it is dropped and
+        // the walk stops too, rather than blocking on a full channel.
+        let (path_tx, path_rx) = mpsc::sync_channel::<PathBuf>(jobs * 16);
+        let path_rx = Arc::new(Mutex::new(path_rx));
+        let walk_tx = result_tx.clone();
+        let walk_progress = progress.clone();
+        scope.spawn(move || {
+            walk_files_parallel(dir, rules, vault, jobs, |path| {
+                walk_progress.inc_length(1);
+                match path {
+                    Ok(path) => path_tx.send(path).is_ok(),
+                    // Straight to the writer, which collects failures.
+                    Err(error) => walk_tx.send(Err(error)).is_ok(),
+                }
+            });
+        });
+
+        for _ in 0..jobs {
+            let result_tx = result_tx.clone();
+            let path_rx = Arc::clone(&path_rx);
+            let indexed = &indexed;
+            scope.spawn(move || {
+                loop {
+                    let Ok(path) = path_rx.lock().unwrap().recv() else {
+                        break;
+                    };
+                    let scanned = scan_file(&path, vault, indexed, force, options);
+                    if result_tx.send(scanned).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_tx);
+        drop(path_rx);
+
+        // Moved in so an early return drops it and stops the workers.
+        let result_rx = result_rx;
+        for scanned in result_rx {
+            progress.inc(1);
+            let scanned = match scanned {
+                Ok(scanned) => scanned,
+                Err(error) => {
+                    report.fail(error);
+                    continue;
+                }
+            };
+            match scanned {
+                Scanned::Unchanged { path, mtime } => {
+                    if !dry_run && indexed.get(&path).is_some_and(|state| state.mtime != mtime) {
+                        touched.push((path.clone(), mtime));
+                        if touched.len() >= WRITE_BATCH {
+                            db.update_mtimes(&vault.name, &touched)?;
+                            touched.clear();
+                        }
+                    }
+                    seen.insert(path);
+                    report.unchanged += 1;
+                }
+                Scanned::Changed { doc, is_new } => {
+                    seen.insert(doc.path.clone());
+                    note_links(&doc.path, &doc.links, &links_before, &mut link_names);
+                    changed.insert(doc.path.clone());
+                    if is_new {
+                        // Written once renames are known, since it may be a
+                        // moved file.
+                        new_docs.push(*doc);
+                    } else if dry_run {
+                        if verbose {
+                            println!("Would index: {}", doc.path);
+                        }
+                        report.updated += 1;
+                    } else {
+                        batch.push(*doc);
+                        if batch.len() >= WRITE_BATCH {
+                            report.updated += write_batch(db, &mut batch, verbose, &mut report);
+                        }
+                    }
+                }
+            }
+        }
+        report.updated += write_batch(db, &mut batch, verbose, &mut report);
+        db.update_mtimes(&vault.name, &touched)?;
+        Ok(())
+    })?;
+    progress.finish_and_clear();
+    let scanned = Instant::now();
+    report.durations.scan = scanned - started;
+
+    // Only rows under the indexed directory are candidates, so indexing a
+    // subfolder leaves the rest of the database alone.
+    let base = vault.relative(&dir.canonicalize()?).unwrap_or_default();
+    let removed: Vec<String> = db
+        .get_paths_under(&vault.name, &base)?
         .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() && path.extension().map_or(false, |ext| ext == "md") {
-            let path_str = path.canonicalize()?.to_string_lossy().to_string();
-
-            if !force {
-                if let Some(db_mtime) = db.get_mtime(&path_str)? {
-                    let file_mtime = fs::metadata(path)?
-                        .modified()?
-                        .duration_since(UNIX_EPOCH)?
-                        .as_secs() as i64;
-                    if file_mtime <= db_mtime {
-                        continue;
+        .filter(|path| !seen.contains(path) && !report.is_failed(path))
+        .collect();
+    for path in &removed {
+        note_links(path, &[], &links_before, &mut link_names);
+    }
+
+    store_new_and_removed(db, &vault, &indexed, new_docs, removed, index, &mut report)?;
+    let stored = Instant::now();
+    report.durations.store = stored - scanned;
+    if !dry_run {
+        update_backlinks(db, &vault, &changed, &link_names, &keys_before)?;
+        report.durations.backlinks = stored.elapsed();
+    }
+    report.durations.total = started.elapsed();
+    Ok(report)
+}
+
+/// Brings the index up to date for `paths` alone: files and folders under
+/// `dir`, given as absolute paths, that were created, changed, moved or
+/// deleted. Backlinks are recomputed only for the notes the changes touch.
+/// Files that fail are skipped as in `index_directory`.
+pub fn index_paths(
+    dir: &Path,
+    db: &Database,
+    paths: &[PathBuf],
+    index: &IndexOptions,
+    options: &ExtractOptions,
+) -> Result<IndexReport, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let base = dir.canonicalize()?;
+    let vault = open_vault(db, &base, index)?;
+    let rules = IgnoreRules::load(&vault.root, &base, &index.include, &index.exclude)?;
+    let indexed = db.get_file_states(&vault.name)?;
+    let mut report = IndexReport {
+        vault: vault.name.clone(),
+        ..Default::default()
+    };
+
+    let mut files: BTreeSet<PathBuf> = BTreeSet::new();
+    // Indexed files at or under one of `paths` that no longer exist or are
+    // now ignored.
+    let mut gone: BTreeSet<String> = BTreeSet::new();
+    for path in paths {
+        let Some(path_str) = vault.relative(path) else {
+            continue;
+        };
+        if path.is_dir() {
+            if !rules.is_ignored_with_parents(path, true) {
+                for file in walk_files(path, &rules, &vault) {
+                    match file {
+                        Ok(file) => {
+                            files.insert(file);
+                        }
+                        Err(error) => report.fail(error),
                     }
                 }
             }
+        } else if path.is_file()
+            && is_indexable(path)
+            && !rules.is_ignored_with_parents(path, false)
+        {
+            files.insert(path.clone());
+        }
 
-            let metadata = fs::metadata(path)?;
-            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-            let parent = path.parent().unwrap().to_string_lossy().to_string();
-
-            let content = fs::read_to_string(path)?;
-            let extracted = Extractor::extract(&content);
-
-            let size = metadata.len();
-            let ctime = metadata.created()?.duration_since(UNIX_EPOCH)?.as_secs() as i64;
-            let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs() as i64;
-
-            let doc = Document {
-                path: path_str,
-                folder: parent,
-                name: file_name.trim_end_matches(".md").to_string(),
-                ext: "md".to_string(),
-                size,
-                ctime,
-                mtime,
-                content: extracted.full_content,
-                tags: extracted.tags,
-                links: extracted.links,
-                backlinks: vec![],
-                embeds: extracted.embeds,
-                properties: extracted.frontmatter,
-            };
+        let stored = db.get_paths_under(&vault.name, &path_str)?;
+        for stored in stored
+            .into_iter()
+            .chain(indexed.contains_key(&path_str).then_some(path_str))
+        {
+            let stored_path = vault.absolute(&stored);
+            if (!stored_path.is_file() || rules.is_ignored_with_parents(&stored_path, false))
+                && !report.is_failed(&stored)
+            {
+                gone.insert(stored);
+            }
+        }
+    }
+    if files.is_empty() && gone.is_empty() {
+        report.durations.total = started.elapsed();
+        return Ok(report);
+    }
 
-            db.upsert_document(&doc)?;
-            if verbose {
-                println!("Indexed: {}", doc.path);
+    let links_before = db.get_all_links(&vault.name)?;
+    let keys_before = db.get_link_keys(&vault.name)?;
+    let mut changed: HashSet<String> = HashSet::new();
+    let mut link_names: HashSet<String> = HashSet::new();
+    let mut batch: Vec<Document> = Vec::new();
+    let mut new_docs: Vec<Document> = Vec::new();
+    let mut touched: Vec<(String, i64)> = Vec::new();
+    for path in &files {
+        let scanned = match scan_file(path, &vault, &indexed, index.force, options) {
+            Ok(scanned) => scanned,
+            Err(error) => {
+                report.fail(error);
+                continue;
+            }
+        };
+        match scanned {
+            Scanned::Unchanged { path, mtime } => {
+                if !index.dry_run && indexed.get(&path).is_some_and(|state| state.mtime != mtime) {
+                    touched.push((path, mtime));
+                }
+                report.unchanged += 1;
+            }
+            Scanned::Changed { doc, is_new } => {
+                note_links(&doc.path, &doc.links, &links_before, &mut link_names);
+                changed.insert(doc.path.clone());
+                if is_new {
+                    new_docs.push(*doc);
+                } else if index.dry_run {
+                    if index.verbose {
+                        println!("Would index: {}", doc.path);
+                    }
+                    report.updated += 1;
+                } else {
+                    batch.push(*doc);
+                }
+            }
+        }
+    }
+    if !index.dry_run {
+        report.updated += write_batch(db, &mut batch, index.verbose, &mut report);
+        db.update_mtimes(&vault.name, &touched)?;
+    }
+    for path in &gone {
+        note_links(path, &[], &links_before, &mut link_names);
+    }
+    let scanned = Instant::now();
+    report.durations.scan = scanned - started;
+
+    store_new_and_removed(
+        db,
+        &vault,
+        &indexed,
+        new_docs,
+        gone.into_iter().collect(),
+        index,
+        &mut report,
+    )?;
+    let stored = Instant::now();
+    report.durations.store = stored - scanned;
+    if !index.dry_run {
+        update_backlinks(db, &vault, &changed, &link_names, &keys_before)?;
+        report.durations.backlinks = stored.elapsed();
+    }
+    report.durations.total = started.elapsed();
+    Ok(report)
+}
+
+/// Prints what a run did and the files that failed, then problems in the
+/// indexed files: extraction errors and broken embeds and block references,
+/// listed with `verbose`.
+pub fn print_report(
+    db: &Database,
+    report: &IndexReport,
+    index: &IndexOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let counts = format!(
+        "{} new, {} changed, {} unchanged",
+        report.added, report.updated, report.unchanged
+    );
+    if index.dry_run {
+        println!("Would index {} files ({})", report.indexed(), counts);
+        println!("Would rename {} files", report.renamed);
+        println!("Would remove {} files", report.deleted);
+        report.print_errors();
+        return Ok(());
+    }
+    println!("Indexed {} files ({})", report.indexed(), counts);
+    println!("Renamed {} files", report.renamed);
+    println!("Removed {} files", report.deleted);
+    report.print_errors();
+    if index.verbose {
+        let durations = &report.durations;
+        println!(
+            "Took {:.2?} (scan {:.2?}, store {:.2?}, backlinks {:.2?})",
+            durations.total, durations.scan, durations.store, durations.backlinks
+        );
+    }
+
+    let documents_with_errors = db.get_document_errors(&report.vault)?;
+    if !documents_with_errors.is_empty() {
+        println!(
+            "Warning: {} files have extraction errors",
+            documents_with_errors.len()
+        );
+        for (path, errors) in &documents_with_errors {
+            for error in errors {
+                println!("  {}: {}", path, error);
+            }
+        }
+    }
+
+    let broken_embeds = db.get_broken_embeds(Some(&report.vault))?;
+    if !broken_embeds.is_empty() {
+        println!("Found {} broken embeds", broken_embeds.len());
+        if index.verbose {
+            for (_, path, embed) in &broken_embeds {
+                println!("Broken embed: {} -> ![[{}]]", path, embed);
+            }
+        }
+    }
+
+    let broken_refs = db.get_broken_block_refs(Some(&report.vault))?;
+    if !broken_refs.is_empty() {
+        println!("Found {} broken block references", broken_refs.len());
+        if index.verbose {
+            for (_, path, link) in &broken_refs {
+                println!("Broken block reference: {} -> [[{}]]", path, link);
             }
-            all_docs.push(doc.clone());
-            count += 1;
         }
     }
+    Ok(())
+}
+
+/// Indexable files under `dir`, leaving out what `rules` ignore, and the
+/// folders and links that could not be listed.
+fn walk_files<'a>(
+    dir: &'a Path,
+    rules: &IgnoreRules,
+    vault: &'a Vault,
+) -> impl Iterator<Item = Result<PathBuf, FileError>> + 'a {
+    walker(dir, rules, vault)
+        .build()
+        .filter_map(move |entry| walked(entry, dir, vault))
+}
+
+/// `walk_files` on `threads` threads, handing each result to `found` as it
+/// comes; the walk stops once `found` returns false. Listing folders is
+/// mostly waiting on the filesystem, which a large or network-mounted vault
+/// makes the longest part of a run with few changes.
+fn walk_files_parallel(
+    dir: &Path,
+    rules: &IgnoreRules,
+    vault: &Vault,
+    threads: usize,
+    found: impl Fn(Result<PathBuf, FileError>) -> bool + Sync,
+) {
+    walker(dir, rules, vault)
+        .threads(threads)
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                if walked(entry, dir, vault).is_none_or(&found) {
+                    WalkState::Continue
+                } else {
+                    WalkState::Quit
+                }
+            })
+        });
+}
+
+fn walker(dir: &Path, rules: &IgnoreRules, vault: &Vault) -> WalkBuilder {
+    // The walk starts at the vault root so the ignore files of the folders
+    // above `dir` are read, but only goes down into `dir`.
+    let (rules, target) = (rules.clone(), dir.to_path_buf());
+    let mut builder = WalkBuilder::new(&vault.root);
+    builder
+        .standard_filters(false)
+        .git_ignore(true)
+        .require_git(false)
+        .add_custom_ignore_filename(IgnoreRules::IGNORE_FILE)
+        .follow_links(true)
+        .filter_entry(move |e| {
+            let is_dir = e.file_type().is_some_and(|t| t.is_dir());
+            (target.starts_with(e.path()) || e.path().starts_with(&target))
+                && !rules.is_ignored(e.path(), is_dir)
+        });
+    builder
+}
 
-    let link_map = db.get_all_links()?;
-    let mut backlinks: std::collections::HashMap<String, Vec<String>> =
-        std::collections::HashMap::new();
+/// What an entry of the walk under `dir` yields: the path of an indexable
+/// file, the error of one that could not be listed, or nothing.
+fn walked(
+    entry: Result<DirEntry, ignore::Error>,
+    dir: &Path,
+    vault: &Vault,
+) -> Option<Result<PathBuf, FileError>> {
+    match entry {
+        Ok(e)
+            if e.path().starts_with(dir)
+                && e.file_type().is_some_and(|t| t.is_file())
+                && is_indexable(e.path()) =>
+        {
+            Some(Ok(e.into_path()))
+        }
+        Ok(_) => None,
+        Err(err) => Some(Err(FileError::new(
+            vault,
+            walk_error_path(&err).unwrap_or(dir),
+            Stage::Walk,
+            &err,
+        ))),
+    }
+}
 
-    for (path, links) in &link_map {
-        for link in links {
-            let link_name = link
-                .trim_end_matches(|c: char| c == '|' || c == '#')
-                .to_string();
-            backlinks.entry(link_name).or_default().push(path.clone());
+/// The file or folder an error from the walk is about.
+fn walk_error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
         }
+        _ => None,
     }
+}
 
-    for doc in &all_docs {
-        if let Some(back_links) = backlinks.get(&doc.name) {
-            let mut updated_doc = doc.clone();
-            updated_doc.backlinks = back_links.clone();
-            db.upsert_document(&updated_doc)?;
+/// Writes files found new and deletes those found gone. A new file with the
+/// content of a gone one is that file moved or renamed: its row is moved so
+/// links to it follow the new path.
+fn store_new_and_removed(
+    db: &Database,
+    vault: &Vault,
+    indexed: &HashMap<String, FileState>,
+    mut new_docs: Vec<Document>,
+    mut removed: Vec<String>,
+    index: &IndexOptions,
+    report: &mut IndexReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let IndexOptions {
+        verbose, dry_run, ..
+    } = *index;
+    // Workers finish in any order.
+    new_docs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut vanished: HashMap<&str, &str> = HashMap::new();
+    for path in &removed {
+        if let Some(state) = indexed.get(path) {
+            vanished.entry(&state.hash).or_insert(path);
+        }
+    }
+    let renamed: Vec<(String, String)> = new_docs
+        .iter()
+        .filter_map(|doc| {
+            Some((
+                vanished.remove(doc.hash.as_str())?.to_string(),
+                doc.path.clone(),
+            ))
+        })
+        .collect();
+    removed.retain(|path| !renamed.iter().any(|(old, _)| old == path));
+    report.renamed += renamed.len();
+    report.deleted += removed.len();
+
+    for (old, new) in &renamed {
+        if dry_run {
+            if verbose {
+                println!("Would rename: {} -> {}", old, new);
+            }
+        } else {
+            db.rename_document(&vault.name, old, new)?;
+            if verbose {
+                println!("Renamed: {} -> {}", old, new);
+            }
+        }
+    }
+    if dry_run {
+        report.added += new_docs.len() - renamed.len();
+        if verbose {
+            for doc in &new_docs {
+                println!("Would index: {}", doc.path);
+            }
+        }
+    } else {
+        // Moved files have a row by now, the rest are bulk loaded.
+        let (mut moved, mut fresh): (Vec<Document>, Vec<Document>) = new_docs
+            .into_iter()
+            .partition(|doc| renamed.iter().any(|(_, new)| *new == doc.path));
+        match db.insert_documents(&fresh) {
+            Ok(()) => {
+                report.added += fresh.len();
+                if verbose {
+                    for doc in &fresh {
+                        println!("Indexed: {}", doc.path);
+                    }
+                }
+            }
+            // Nothing was loaded: some file was indexed since the run read
+            // the index, so the batch is written over what is stored.
+            Err(err) if is_conflict(err.as_ref()) => {
+                report.added += write_batch(db, &mut fresh, verbose, report);
+            }
+            Err(err) => return Err(err),
         }
+        write_batch(db, &mut moved, verbose, report);
     }
 
-    println!("Indexed {} files", count);
+    for path in &removed {
+        if dry_run {
+            if verbose {
+                println!("Would remove: {}", path);
+            }
+        } else {
+            db.delete_document(&vault.name, path)?;
+            if verbose {
+                println!("Removed: {}", path);
+            }
+        }
+    }
     Ok(())
 }
 
+/// Adds the names the file at `path` links to, both `links` as it is now
+/// and as indexed in `links_before`.
+fn note_links(
+    path: &str,
+    links: &[String],
+    links_before: &HashMap<String, Vec<String>>,
+    link_names: &mut HashSet<String>,
+) {
+    let before = links_before.get(path).into_iter().flatten();
+    for link in links.iter().chain(before) {
+        link_names.extend(link_names_of(link).map(str::to_string));
+    }
+}
+
+/// Recomputes the backlinks of the files at `paths`, of every file linked
+/// as one of `link_names` and of every file whose link keys differ from
+/// `keys_before`, as when another attachment now shares its name, from the
+/// links of all files in `vault`.
+fn update_backlinks(
+    db: &Database,
+    vault: &Vault,
+    paths: &HashSet<String>,
+    link_names: &HashSet<String>,
+    keys_before: &HashMap<String, Vec<String>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut backlinks: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, links) in db.get_all_links(&vault.name)? {
+        for link in &links {
+            for name in link_names_of(link) {
+                backlinks
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+    }
+
+    let mut updates: Vec<(String, Vec<String>)> = Vec::new();
+    for (path, keys) in db.get_link_keys(&vault.name)? {
+        if !paths.contains(&path)
+            && keys_before.get(&path) == Some(&keys)
+            && !keys.iter().any(|key| link_names.contains(key))
+        {
+            continue;
+        }
+        let mut back_links: Vec<String> = Vec::new();
+        for key in &keys {
+            for source in backlinks.get(key).into_iter().flatten() {
+                if !back_links.contains(source) {
+                    back_links.push(source.clone());
+                }
+            }
+        }
+        updates.push((path, back_links));
+    }
+    db.set_backlinks(&vault.name, &updates)
+}
+
+/// Writes changed files in one transaction and empties `batch`. When the
+/// transaction fails, the files are written one at a time so only those
+/// that fail themselves are reported. Returns the number written.
+fn write_batch(
+    db: &Database,
+    batch: &mut Vec<Document>,
+    verbose: bool,
+    report: &mut IndexReport,
+) -> usize {
+    if db.replace_documents(batch).is_err() {
+        batch.retain(
+            |doc| match db.replace_documents(std::slice::from_ref(doc)) {
+                Ok(()) => true,
+                Err(err) => {
+                    report.fail(FileError {
+                        path: doc.path.clone(),
+                        stage: Stage::Write,
+                        error: err.to_string(),
+                    });
+                    false
+                }
+            },
+        );
+    }
+    if verbose {
+        for doc in batch.iter() {
+            println!("Indexed: {}", doc.path);
+        }
+    }
+    let written = batch.len();
+    batch.clear();
+    written
+}
+
+fn is_indexable(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    ext == "md" || ext == "canvas" || attachments::mime_type(ext).is_some()
+}
+
+/// Reads, hashes and, unless it is already indexed, extracts one file. A
+/// file whose size and mtime match the index is taken as unchanged unread.
+fn scan_file(
+    path: &Path,
+    vault: &Vault,
+    indexed: &HashMap<String, FileState>,
+    force: bool,
+    options: &ExtractOptions,
+) -> Result<Scanned, FileError> {
+    let read_error = |err: std::io::Error| FileError::new(vault, path, Stage::Read, err);
+    let path_str = vault
+        .relative(path)
+        .ok_or_else(|| FileError::new(vault, path, Stage::Read, "not inside the vault"))?;
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let is_attachment = attachments::mime_type(ext).is_some();
+
+    let metadata = fs::metadata(path).map_err(read_error)?;
+    let modified = metadata.modified().map_err(read_error)?;
+    let mtime = unix_seconds(modified);
+    let size = metadata.len();
+
+    // A file whose size and mtime both match the index is not read again.
+    // Otherwise content decides, as timestamps move on checkout or sync.
+    // With `force` an indexed file is extracted again all the same, but it
+    // is still an update rather than an addition.
+    let is_new = !indexed.contains_key(&path_str);
+    let stored = indexed.get(&path_str).filter(|_| !force);
+    if stored.is_some_and(|state| state.size == size && state.mtime == mtime) {
+        return Ok(Scanned::Unchanged {
+            path: path_str,
+            mtime,
+        });
+    }
+    // Attachments can be large and are only hashed, so they are streamed;
+    // text is read whole to be extracted.
+    let bytes = if is_attachment {
+        None
+    } else {
+        Some(fs::read(path).map_err(read_error)?)
+    };
+    let hash = match &bytes {
+        Some(bytes) => attachments::content_hash(bytes),
+        None => attachments::file_hash(path).map_err(read_error)?,
+    };
+    if stored.is_some_and(|state| state.hash == hash) {
+        return Ok(Scanned::Unchanged {
+            path: path_str,
+            mtime,
+        });
+    }
+
+    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+    let folder = vault::folder_of(&path_str).to_string();
+
+    let (extracted, canvas, attachment) = match bytes {
+        None => {
+            let extracted = Extractor::extract("", options);
+            (extracted, Canvas::default(), Attachment::read(ext, path))
+        }
+        Some(bytes) => {
+            let (content, encoding) = decode(&bytes);
+            let (mut extracted, canvas) = if ext == "canvas" {
+                extract_canvas(&content, options)
+            } else {
+                (Extractor::extract(&content, options), Canvas::default())
+            };
+            if let Some(encoding) = encoding {
+                extracted.errors.push(ExtractError {
+                    kind: "encoding".to_string(),
+                    message: format!("not valid UTF-8, read as {}", encoding),
+                    line: None,
+                    column: None,
+                });
+            }
+            (extracted, canvas, None)
+        }
+    };
+
+    // Not every filesystem records creation time.
+    let ctime = unix_seconds(metadata.created().unwrap_or(modified));
+    let (date, date_source) = dates::infer_date(
+        &name,
+        &extracted.properties,
+        ctime,
+        &options.periodic_formats,
+    );
+
+    let doc = Document {
+        vault: vault.name.clone(),
+        path: path_str,
+        folder,
+        name,
+        ext: ext.to_string(),
+        size,
+        ctime,
+        mtime,
+        hash,
+        date,
+        date_source,
+        content: (!options.skip_content && attachment.is_none()).then_some(extracted.full_content),
+        body: extracted.body,
+        plain_text: extracted.plain_text,
+        tags: extracted.tags,
+        tag_sources: extracted.tag_sources,
+        aliases: extracted.aliases,
+        links: extracted.links,
+        backlinks: vec![],
+        embeds: extracted.embeds,
+        highlights: extracted.highlights,
+        footnotes: extracted.footnotes,
+        comments: extracted.comments,
+        custom_fields: extracted.custom_fields,
+        blocks: extracted.blocks,
+        callouts: extracted.callouts,
+        code_blocks: extracted.code_blocks,
+        canvas_nodes: canvas.nodes,
+        canvas_edges: canvas.edges,
+        attachment,
+        words: extracted.stats.words as u64,
+        chars: extracted.stats.chars as u64,
+        lines: extracted.stats.lines as u64,
+        reading_time: extracted.stats.reading_time as u64,
+        title: extracted.stats.title,
+        excerpt: extracted.stats.excerpt,
+        properties: extracted.properties,
+        frontmatter_format: extracted.frontmatter_format,
+        errors: extracted.errors,
+        property_sources: extracted.property_sources,
+    };
+
+    Ok(Scanned::Changed {
+        doc: Box::new(doc),
+        is_new,
+    })
+}
+
+/// The vault to index `dir` into, registering it unless `dry_run`:
+///
+/// - with a `vault` name, the vault of that name, moved to `dir` unless
+///   `dir` is inside it, which takes `move_vault` while its old root still
+///   exists, or a new one;
+/// - otherwise the vault `dir` is in, to index a subfolder, or the vault
+///   named after `dir`, moved to it when its old root is gone, or a new one.
+pub fn open_vault(
+    db: &Database,
+    dir: &Path,
+    index: &IndexOptions,
+) -> Result<Vault, Box<dyn std::error::Error>> {
+    let dir = dir.canonicalize()?;
+    let vaults = db.get_vaults()?;
+    let vault = match index.vault.as_deref() {
+        Some(name) => {
+            vault::check_name(name)?;
+            Vault {
+                name: name.to_string(),
+                root: dir.clone(),
+            }
+        }
+        None => {
+            // The innermost one, should vaults be nested.
+            if let Some(vault) = vaults
+                .iter()
+                .filter(|vault| dir.starts_with(&vault.root))
+                .max_by_key(|vault| vault.root.components().count())
+            {
+                return Ok(vault.clone());
+            }
+            let name = dir.file_name().map_or("vault".to_string(), |name| {
+                vault::name_from(&name.to_string_lossy())
+            });
+            if let Some(existing) = vaults.iter().find(|vault| vault.name == name)
+                && existing.root.is_dir()
+            {
+                return Err(format!(
+                    "vault '{}' is already indexed from {}; pass --vault to name this one",
+                    name,
+                    existing.root.display()
+                )
+                .into());
+            }
+            Vault {
+                name,
+                root: dir.clone(),
+            }
+        }
+    };
+    if let Some(existing) = vaults.into_iter().find(|v| v.name == vault.name) {
+        if dir.starts_with(&existing.root) {
+            return Ok(existing);
+        }
+        if existing.root.is_dir() && !index.move_vault {
+            return Err(format!(
+                "vault '{}' is indexed from {}, which still exists; pass --move-vault to move it to {}",
+                existing.name,
+                existing.root.display(),
+                dir.display()
+            )
+            .into());
+        }
+    }
+    if !index.dry_run {
+        db.set_vault(&vault)?;
+    }
+    Ok(vault)
+}
+
+/// Seconds since the Unix epoch, negative before it.
+fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
+/// Decodes a text file: as UTF-8 when it is, dropping a byte order mark,
+/// otherwise in the encoding its byte order mark names or, failing that,
+/// the one its bytes most likely are, such as Windows-1252. Returns the
+/// name of the encoding when it is not UTF-8.
+fn decode(bytes: &[u8]) -> (String, Option<&'static str>) {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None if std::str::from_utf8(bytes).is_ok() => UTF_8,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            detector.guess(None, true)
+        }
+    };
+    // Sniffs and strips the byte order mark, and replaces what does not
+    // decode.
+    let (text, encoding, _) = encoding.decode(bytes);
+    (
+        text.into_owned(),
+        (encoding != UTF_8).then_some(encoding.name()),
+    )
+}
+
+/// Canvas files are indexed through the text of their cards, with file
+/// cards counted as links. A canvas that fails to parse is still indexed and
+/// keeps the error.
+fn extract_canvas(json: &str, options: &ExtractOptions) -> (ExtractedContent, Canvas) {
+    let (mut extracted, canvas) = match Canvas::parse(json) {
+        Ok(canvas) => {
+            let mut extracted = Extractor::extract(&canvas.markdown(), options);
+            extracted.links.extend(canvas.file_links());
+            (extracted, canvas)
+        }
+        Err(e) => {
+            let mut extracted = Extractor::extract("", options);
+            let message = e.to_string();
+            extracted.errors.push(ExtractError {
+                kind: "canvas".to_string(),
+                message: message
+                    .split(" at line ")
+                    .next()
+                    .unwrap_or(&message)
+                    .to_string(),
+                line: Some(e.line()),
+                column: Some(e.column()),
+            });
+            (extracted, Canvas::default())
+        }
+    };
+    extracted.full_content = json.to_string();
+    (extracted, canvas)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::Database;
+    use crate::db::{Database, link_target};
     use std::fs;
     use std::io::Write;
     use std::path::PathBuf;
@@ -141,6 +1177,15 @@ mod tests {
         (test_dir, db_path)
     }
 
+    /// The vault of a database that only has one.
+    fn vault_name(db: &Database) -> String {
+        db.get_vaults().unwrap().remove(0).name
+    }
+
+    fn stored_mtime(db: &Database, path: &str) -> Option<i64> {
+        db.get_mtime(&vault_name(db), path).unwrap()
+    }
+
     fn cleanup(test_dir: &Path, db_path: &Path) {
         let _ = fs::remove_dir_all(test_dir);
         let _ = fs::remove_file(db_path);
@@ -156,18 +1201,15 @@ mod tests {
         create_test_file(&test_dir, "test.md", "# Test\n\nContent here.");
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
         assert!(result.is_ok());
 
-        let mtime = db
-            .get_mtime(
-                &test_dir
-                    .join("test.md")
-                    .canonicalize()
-                    .unwrap()
-                    .to_string_lossy(),
-            )
-            .unwrap();
+        let mtime = stored_mtime(&db, "test.md");
         assert!(mtime.is_some());
 
         cleanup(&test_dir, &db_path);
@@ -182,10 +1224,15 @@ mod tests {
         create_test_file(&test_dir, "file3.md", "# File 3");
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
         assert!(result.is_ok());
 
-        let link_map = db.get_all_links().unwrap();
+        let link_map = db.get_all_links(&vault_name(&db)).unwrap();
         assert_eq!(link_map.len(), 3);
 
         cleanup(&test_dir, &db_path);
@@ -202,10 +1249,15 @@ mod tests {
         create_test_file(&subdir, "sub.md", "# Sub");
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
         assert!(result.is_ok());
 
-        let link_map = db.get_all_links().unwrap();
+        let link_map = db.get_all_links(&vault_name(&db)).unwrap();
         assert_eq!(link_map.len(), 2);
 
         cleanup(&test_dir, &db_path);
@@ -220,89 +1272,301 @@ mod tests {
         create_test_file(&test_dir, "data.json", "{}");
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
         assert!(result.is_ok());
 
-        let link_map = db.get_all_links().unwrap();
+        let link_map = db.get_all_links(&vault_name(&db)).unwrap();
         assert_eq!(link_map.len(), 1);
 
         cleanup(&test_dir, &db_path);
     }
 
     #[test]
-    fn test_index_with_frontmatter() {
+    fn test_index_records_frontmatter_errors() {
         let (test_dir, db_path) = create_test_directory();
 
-        let content = r#"---
-title: Test
-tags: [test, example]
----
-
-# Test Content
+        create_test_file(&test_dir, "broken.md", "---\ninvalid: yaml: [\n---\n\nBody");
+        create_test_file(&test_dir, "fine.md", "---\ntitle: Fine\n---\n\nBody");
 
-See [[other]] for more."#;
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        let documents = db.get_document_errors(&vault_name(&db)).unwrap();
+        assert_eq!(documents.len(), 1);
+        let (path, errors) = documents.iter().next().unwrap();
+        assert!(path.ends_with("broken.md"));
+        assert_eq!(errors[0].kind, "yaml-frontmatter");
+
+        let sql = crate::query::build_sql("file.errors =~ '%yaml-frontmatter%'", "name").unwrap();
+        let results = db.query(&sql, "name", 10).unwrap();
+        assert_eq!(results, vec![vec!["broken".to_string()]]);
 
-        create_test_file(&test_dir, "with_frontmatter.md", content);
+        cleanup(&test_dir, &db_path);
+    }
 
-        let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
-        assert!(result.is_ok());
+    #[test]
+    fn test_index_comments_option() {
+        let (test_dir, db_path) = create_test_directory();
 
-        let link_map = db.get_all_links().unwrap();
-        assert_eq!(link_map.len(), 1);
+        create_test_file(&test_dir, "notes.md", "Text ==marked== %%todo: rewrite%%");
 
-        let links = &link_map[&test_dir
-            .join("with_frontmatter.md")
-            .canonicalize()
-            .unwrap()
-            .to_string_lossy()
-            .to_string()];
-        assert!(links.contains(&"other".to_string()));
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        let results = db
+            .query("SELECT highlights, comments FROM documents", "*", 10)
+            .unwrap();
+        assert_eq!(results[0][0], r#"["marked"]"#);
+        assert_eq!(results[0][1], "[]");
+
+        let options = ExtractOptions {
+            index_comments: true,
+            ..Default::default()
+        };
+        let force = IndexOptions {
+            force: true,
+            ..Default::default()
+        };
+        index_directory(&test_dir, &db, &force, &options).unwrap();
+        let sql = crate::query::build_sql("has(file.comments, 'todo: rewrite')", "name").unwrap();
+        let results = db.query(&sql, "name", 10).unwrap();
+        assert_eq!(results, vec![vec!["notes".to_string()]]);
 
         cleanup(&test_dir, &db_path);
     }
 
     #[test]
-    fn test_index_with_backlinks() {
+    fn test_index_custom_extractors() {
         let (test_dir, db_path) = create_test_directory();
 
-        create_test_file(&test_dir, "target.md", "# Target");
-        create_test_file(&test_dir, "referrer.md", "See [[target]] for info.");
+        create_test_file(&test_dir, "a.md", "Blocked on JIRA-12, ask @ana");
+        create_test_file(&test_dir, "b.md", "Nothing to track");
 
-        let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
-        assert!(result.is_ok());
+        let config = crate::config::Config::parse(
+            "[[extractors]]\nname = \"tickets\"\npattern = '[A-Z]+-\\d+'\n",
+        )
+        .unwrap();
+        let options = ExtractOptions {
+            extractors: config.field_extractors().unwrap(),
+            ..Default::default()
+        };
 
-        // Verify both files are indexed
-        let link_map = db.get_all_links().unwrap();
-        assert_eq!(link_map.len(), 2);
+        let db = Database::new(&db_path).unwrap();
+        index_directory(&test_dir, &db, &IndexOptions::default(), &options).unwrap();
+
+        let sql =
+            crate::query::build_sql("has(file.tickets, 'JIRA-12')", "name,file.tickets").unwrap();
+        let results = db.query(&sql, "name,file.tickets", 10).unwrap();
+        assert_eq!(
+            results,
+            vec![vec!["a".to_string(), r#"["JIRA-12"]"#.to_string()]]
+        );
 
         cleanup(&test_dir, &db_path);
     }
 
     #[test]
-    fn test_index_with_tags() {
+    fn test_index_body_without_content() {
         let (test_dir, db_path) = create_test_directory();
 
         create_test_file(
             &test_dir,
-            "tagged.md",
-            "# Title\n\nContent with #tag1 and #tag2.",
+            "doc.md",
+            "---\ntitle: Secret\n---\nA *title* here",
         );
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
-        assert!(result.is_ok());
-
-        let mtime = db
-            .get_mtime(
-                &test_dir
-                    .join("tagged.md")
-                    .canonicalize()
-                    .unwrap()
-                    .to_string_lossy(),
-            )
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        let sql = crate::query::build_sql("file.body =~ '%title%'", "file.plain_text").unwrap();
+        let results = db.query(&sql, "file.plain_text", 10).unwrap();
+        assert_eq!(results, vec![vec!["A title here".to_string()]]);
+        let sql = crate::query::build_sql("file.body =~ '%Secret%'", "name").unwrap();
+        assert!(db.query(&sql, "name", 10).unwrap().is_empty());
+
+        let options = ExtractOptions {
+            skip_content: true,
+            ..Default::default()
+        };
+        let force = IndexOptions {
+            force: true,
+            ..Default::default()
+        };
+        index_directory(&test_dir, &db, &force, &options).unwrap();
+        let results = db
+            .query("SELECT content IS NULL, plain_text FROM documents", "*", 10)
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![vec!["true".to_string(), "A title here".to_string()]]
+        );
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_infers_dates() {
+        let (test_dir, db_path) = create_test_directory();
+
+        create_test_file(&test_dir, "2024-01-15.md", "Daily");
+        create_test_file(&test_dir, "2024-W03.md", "Weekly");
+        create_test_file(&test_dir, "trip.md", "---\ndate: 2023-06-02\n---\nTrip");
+        create_test_file(&test_dir, "misc.md", "No date");
+
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        let sql = crate::query::build_sql(
+            "file.date >= '2023-01-01' and file.date < '2024-02-01'",
+            "name,file.date,file.date_source",
+        )
+        .unwrap();
+        let results = db
+            .query(&format!("{} ORDER BY date, name", sql), "*", 10)
             .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                vec!["trip", "2023-06-02", "frontmatter"],
+                vec!["2024-01-15", "2024-01-15", "filename"],
+                vec!["2024-W03", "2024-01-15", "filename"],
+            ]
+            .into_iter()
+            .map(|row| row.into_iter().map(String::from).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+        );
+
+        let sql = crate::query::build_sql("name == 'misc'", "file.date_source").unwrap();
+        let results = db.query(&sql, "file.date_source", 10).unwrap();
+        assert_eq!(results, vec![vec!["filesystem".to_string()]]);
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_with_frontmatter() {
+        let (test_dir, db_path) = create_test_directory();
+
+        let content = r#"---
+title: Test
+tags: [test, example]
+---
+
+# Test Content
+
+See [[other]] for more."#;
+
+        create_test_file(&test_dir, "with_frontmatter.md", content);
+
+        let db = Database::new(&db_path).unwrap();
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
+        assert!(result.is_ok());
+
+        let link_map = db.get_all_links(&vault_name(&db)).unwrap();
+        assert_eq!(link_map.len(), 1);
+
+        let links = &link_map["with_frontmatter.md"];
+        assert!(links.contains(&"other".to_string()));
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_with_backlinks() {
+        let (test_dir, db_path) = create_test_directory();
+
+        create_test_file(&test_dir, "target.md", "# Target");
+        create_test_file(&test_dir, "referrer.md", "See [[target]] for info.");
+
+        let db = Database::new(&db_path).unwrap();
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
+        assert!(result.is_ok());
+
+        // Verify both files are indexed
+        let link_map = db.get_all_links(&vault_name(&db)).unwrap();
+        assert_eq!(link_map.len(), 2);
+
+        // Editing the referrer updates the backlinks of the unchanged target.
+        let backlinks = |db: &Database| {
+            db.query(
+                "SELECT backlinks FROM documents WHERE name = 'target'",
+                "*",
+                10,
+            )
+            .unwrap()[0][0]
+                .clone()
+        };
+        assert!(backlinks(&db).contains("referrer.md"));
+        create_test_file(&test_dir, "referrer.md", "No links anymore.");
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(backlinks(&db), "[]");
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_with_tags() {
+        let (test_dir, db_path) = create_test_directory();
+
+        create_test_file(
+            &test_dir,
+            "tagged.md",
+            "# Title\n\nContent with #tag1 and #tag2.",
+        );
+
+        let db = Database::new(&db_path).unwrap();
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
+        assert!(result.is_ok());
+
+        let mtime = stored_mtime(&db, "tagged.md");
         assert!(mtime.is_some());
 
         cleanup(&test_dir, &db_path);
@@ -319,18 +1583,15 @@ See [[other]] for more."#;
         );
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
         assert!(result.is_ok());
 
-        let mtime = db
-            .get_mtime(
-                &test_dir
-                    .join("with_embeds.md")
-                    .canonicalize()
-                    .unwrap()
-                    .to_string_lossy(),
-            )
-            .unwrap();
+        let mtime = stored_mtime(&db, "with_embeds.md");
         assert!(mtime.is_some());
 
         cleanup(&test_dir, &db_path);
@@ -341,39 +1602,36 @@ See [[other]] for more."#;
         let (test_dir, db_path) = create_test_directory();
 
         create_test_file(&test_dir, "test.md", "# Original");
+        create_test_file(&test_dir, "other.md", "# Other");
 
         let db = Database::new(&db_path).unwrap();
 
         // First index
-        index_directory(&test_dir, &db, false, false).unwrap();
-        let mtime1 = db
-            .get_mtime(
-                &test_dir
-                    .join("test.md")
-                    .canonicalize()
-                    .unwrap()
-                    .to_string_lossy(),
-            )
-            .unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        let mtime1 = stored_mtime(&db, "test.md");
 
         // Wait a bit and update file
         std::thread::sleep(std::time::Duration::from_millis(100));
         create_test_file(&test_dir, "test.md", "# Updated");
 
         // Re-index with force
-        index_directory(&test_dir, &db, true, false).unwrap();
-        let mtime2 = db
-            .get_mtime(
-                &test_dir
-                    .join("test.md")
-                    .canonicalize()
-                    .unwrap()
-                    .to_string_lossy(),
-            )
-            .unwrap();
+        let force = IndexOptions {
+            force: true,
+            ..Default::default()
+        };
+        let report = index_directory(&test_dir, &db, &force, &ExtractOptions::default()).unwrap();
+        let mtime2 = stored_mtime(&db, "test.md");
 
         // Should have been updated
         assert!(mtime2.unwrap() >= mtime1.unwrap());
+        // Every file is extracted again, and counted as updated, not added.
+        assert_eq!((report.added, report.updated, report.unchanged), (0, 2, 0));
 
         cleanup(&test_dir, &db_path);
     }
@@ -383,12 +1641,919 @@ See [[other]] for more."#;
         let (test_dir, db_path) = create_test_directory();
 
         let db = Database::new(&db_path).unwrap();
-        let result = index_directory(&test_dir, &db, false, false);
+        let result = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        );
         assert!(result.is_ok());
 
-        let link_map = db.get_all_links().unwrap();
+        let link_map = db.get_all_links(&vault_name(&db)).unwrap();
         assert!(link_map.is_empty());
 
         cleanup(&test_dir, &db_path);
     }
+
+    #[test]
+    fn test_link_target() {
+        assert_eq!(link_target("architecture"), "architecture");
+        assert_eq!(
+            link_target("architecture|System Architecture"),
+            "architecture"
+        );
+        assert_eq!(link_target("architecture#Overview"), "architecture");
+        assert_eq!(link_target("architecture#Overview|Intro"), "architecture");
+    }
+
+    #[test]
+    fn test_index_backlinks_through_aliases() {
+        let (test_dir, db_path) = create_test_directory();
+
+        create_test_file(
+            &test_dir,
+            "target.md",
+            "---\naliases: [Main Target]\n---\n\n# Target",
+        );
+        create_test_file(&test_dir, "by_name.md", "See [[target#Intro]].");
+        create_test_file(&test_dir, "by_alias.md", "See [[Main Target|the target]].");
+
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        let results = db
+            .query(
+                "SELECT to_json(backlinks) FROM documents WHERE name = 'target'",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0][0].contains("by_name.md"));
+        assert!(results[0][0].contains("by_alias.md"));
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_canvas() {
+        let (test_dir, db_path) = create_test_directory();
+
+        create_test_file(&test_dir, "spec.md", "# Spec");
+        create_test_file(
+            &test_dir,
+            "plan.canvas",
+            r##"{
+                "nodes": [
+                    {"id": "a", "type": "text", "text": "Ship #q3, see [[roadmap]]", "x": 0, "y": 0, "width": 200, "height": 60},
+                    {"id": "b", "type": "file", "file": "spec.md", "x": 300.5, "y": -12.75, "width": 400, "height": 400}
+                ],
+                "edges": [{"id": "e1", "fromNode": "a", "toNode": "b", "label": "details"}]
+            }"##,
+        );
+        create_test_file(&test_dir, "broken.canvas", "{\"nodes\": [");
+
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        let sql =
+            crate::query::build_sql("ext == 'canvas' and has(tags, 'q3')", "name,links").unwrap();
+        let results = db.query(&sql, "name,links", 10).unwrap();
+        assert_eq!(
+            results,
+            vec![vec![
+                "plan".to_string(),
+                r#"["roadmap","spec"]"#.to_string()
+            ]]
+        );
+
+        let results = db
+            .query(
+                "SELECT to_json(backlinks) FROM documents WHERE name = 'spec'",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert!(results[0][0].contains("plan.canvas"));
+
+        let sql = crate::query::build_sql_from(
+            crate::query::RowSource::CanvasNodes,
+            "node.type == 'file'",
+            "name,node.id,node.file,node.x,node.y",
+        )
+        .unwrap();
+        let results = db.query(&sql, "*", 10).unwrap();
+        assert_eq!(
+            results,
+            vec![vec![
+                "plan".to_string(),
+                "b".to_string(),
+                "spec.md".to_string(),
+                "300.5".to_string(),
+                "-12.75".to_string()
+            ]]
+        );
+
+        let sql = crate::query::build_sql_from(
+            crate::query::RowSource::CanvasEdges,
+            "edge.label == 'details'",
+            "edge.from,edge.to",
+        )
+        .unwrap();
+        let results = db.query(&sql, "*", 10).unwrap();
+        assert_eq!(results, vec![vec!["a".to_string(), "b".to_string()]]);
+
+        let errors = db.get_document_errors(&vault_name(&db)).unwrap();
+        let (path, errors) = errors.iter().next().unwrap();
+        assert!(path.ends_with("broken.canvas"));
+        assert_eq!(errors[0].kind, "canvas");
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_attachments() {
+        let (test_dir, db_path) = create_test_directory();
+
+        fs::create_dir_all(test_dir.join("assets")).unwrap();
+        fs::create_dir_all(test_dir.join("old")).unwrap();
+        create_test_file(&test_dir, "assets/mockup.png", "not really a png");
+        create_test_file(&test_dir, "old/mockup.png", "an older mockup");
+        create_test_file(&test_dir, "spec.pdf", "%PDF-1.7");
+        create_test_file(&test_dir, "notes.txt", "not indexed");
+        create_test_file(
+            &test_dir,
+            "design.md",
+            "![[assets/mockup.png]]\n![[missing.jpg|200]]\n![[spec.pdf]]\n![[mockup.png]]\n![[wrong/mockup.png]]",
+        );
+
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        // Two attachments share a file name, so the embed resolves by folder.
+        let sql = crate::query::build_sql("ext != 'md'", "path,backlinks").unwrap();
+        let mut results = db.query(&sql, "*", 10).unwrap();
+        results.sort();
+        assert_eq!(
+            results,
+            vec![
+                vec![
+                    "assets/mockup.png".to_string(),
+                    r#"["design.md"]"#.to_string()
+                ],
+                vec!["old/mockup.png".to_string(), "[]".to_string()],
+                vec!["spec.pdf".to_string(), r#"["design.md"]"#.to_string()],
+            ]
+        );
+
+        let unused = db.get_attachments(true, None).unwrap();
+        assert_eq!(unused.len(), 1);
+        assert_eq!(unused[0].1, "old/mockup.png");
+        assert_eq!(unused[0].2.mime, "image/png");
+
+        let sql = crate::query::build_sql_from(
+            crate::query::RowSource::Attachments,
+            "attachment.mime == 'application/pdf'",
+            "path,attachment.hash,file.hash",
+        )
+        .unwrap();
+        let hash = attachments::content_hash(b"%PDF-1.7");
+        assert_eq!(
+            db.query(&sql, "*", 10).unwrap(),
+            vec![vec!["spec.pdf".to_string(), hash.clone(), hash]]
+        );
+
+        // A shared file name alone, or in the wrong folder, resolves to no
+        // attachment.
+        let broken: Vec<String> = db
+            .get_broken_embeds(None)
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, embed)| embed)
+            .collect();
+        assert_eq!(
+            broken,
+            vec!["missing.jpg|200", "mockup.png", "wrong/mockup.png"]
+        );
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_attachment_sharing_a_name() {
+        let (test_dir, db_path) = create_test_directory();
+
+        fs::create_dir_all(test_dir.join("assets")).unwrap();
+        create_test_file(&test_dir, "assets/chart.png", "first chart");
+        create_test_file(&test_dir, "report.md", "![[chart.png]]");
+
+        let db = Database::new(&db_path).unwrap();
+        let backlinks = |db: &Database| {
+            db.query(
+                "SELECT backlinks FROM documents WHERE path = 'assets/chart.png'",
+                "*",
+                10,
+            )
+            .unwrap()[0][0]
+                .clone()
+        };
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(backlinks(&db), r#"["report.md"]"#);
+
+        // Once another attachment has the name, the bare embed resolves to
+        // neither, though neither the note nor the first chart changed.
+        fs::create_dir_all(test_dir.join("old")).unwrap();
+        create_test_file(&test_dir, "old/chart.png", "older chart");
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(backlinks(&db), "[]");
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_removes_deleted_files() {
+        let (test_dir, db_path) = create_test_directory();
+
+        fs::create_dir_all(test_dir.join("sub")).unwrap();
+        create_test_file(&test_dir, "keep.md", "See [[gone]]");
+        let gone = create_test_file(&test_dir, "gone.md", "See [[keep]]");
+        let nested = create_test_file(&test_dir, "sub/nested.md", "# Nested");
+
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        fs::remove_file(&gone).unwrap();
+        fs::remove_file(&nested).unwrap();
+
+        // Indexing a subfolder only purges files inside it.
+        index_directory(
+            &test_dir.join("sub"),
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert!(stored_mtime(&db, "gone.md").is_some());
+        assert!(stored_mtime(&db, "sub/nested.md").is_none());
+
+        let dry_run = IndexOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        index_directory(&test_dir, &db, &dry_run, &ExtractOptions::default()).unwrap();
+        assert!(stored_mtime(&db, "gone.md").is_some());
+
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert!(stored_mtime(&db, "gone.md").is_none());
+        let results = db
+            .query(
+                "SELECT backlinks FROM documents WHERE name = 'keep'",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results, vec![vec!["[]".to_string()]]);
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_detects_changes_and_renames_by_content() {
+        let (test_dir, db_path) = create_test_directory();
+
+        let a = create_test_file(&test_dir, "a.md", "See [[b]]");
+        create_test_file(&test_dir, "b.md", "# B");
+
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        // Rewritten within the same second: the size differs, so the file is
+        // hashed again and the new content indexed.
+        let b = create_test_file(&test_dir, "b.md", "# B2");
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        let results = db
+            .query("SELECT title FROM documents WHERE name = 'b'", "*", 10)
+            .unwrap();
+        assert_eq!(results, vec![vec!["B2".to_string()]]);
+
+        // Same size and mtime: skipped without reading, unless forced.
+        let modified = fs::metadata(&b).unwrap().modified().unwrap();
+        create_test_file(&test_dir, "b.md", "# B3");
+        fs::File::options()
+            .write(true)
+            .open(&b)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let report = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.unchanged, 2);
+        let force = IndexOptions {
+            force: true,
+            ..Default::default()
+        };
+        index_directory(&test_dir, &db, &force, &ExtractOptions::default()).unwrap();
+        let results = db
+            .query("SELECT title FROM documents WHERE name = 'b'", "*", 10)
+            .unwrap();
+        assert_eq!(results, vec![vec!["B3".to_string()]]);
+
+        fs::create_dir_all(test_dir.join("sub")).unwrap();
+        fs::rename(&a, test_dir.join("sub/a.md")).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        let results = db
+            .query(
+                "SELECT path, to_json(backlinks) FROM documents ORDER BY path",
+                "*",
+                10,
+            )
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], vec!["b.md", r#"["sub/a.md"]"#]);
+        assert_eq!(results[1][0], "sub/a.md");
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_honors_ignore_rules() {
+        let (test_dir, db_path) = create_test_directory();
+
+        for folder in [
+            ".obsidian",
+            ".trash",
+            "node_modules/pkg",
+            "Templates",
+            "drafts",
+            "projects/private",
+            "projects/drafts",
+        ] {
+            fs::create_dir_all(test_dir.join(folder)).unwrap();
+        }
+        create_test_file(
+            &test_dir,
+            ".obsidian/app.json",
+            r#"{"userIgnoreFilters": ["Templates/", "projects/private/"]}"#,
+        );
+        create_test_file(&test_dir, ".gitignore", "drafts/\n");
+        create_test_file(&test_dir, ".mdbignore", "*.tmp.md\n");
+        create_test_file(&test_dir, ".obsidian/workspace.md", "# Hidden");
+        create_test_file(&test_dir, ".trash/deleted.md", "# Deleted");
+        create_test_file(&test_dir, "node_modules/pkg/README.md", "# Package");
+        create_test_file(&test_dir, "Templates/daily.md", "# Template");
+        create_test_file(&test_dir, "drafts/idea.md", "# Draft");
+        create_test_file(&test_dir, "scratch.tmp.md", "# Scratch");
+        create_test_file(&test_dir, "note.md", "# Note");
+        create_test_file(&test_dir, "archived.md", "# Archived");
+        // A nested ignore file applies to its own folder.
+        create_test_file(&test_dir, "projects/.gitignore", "*.log.md\n");
+        create_test_file(&test_dir, "projects/plan.md", "# Plan");
+        create_test_file(&test_dir, "projects/build.log.md", "# Log");
+        create_test_file(&test_dir, "projects/private/secret.md", "# Secret");
+        create_test_file(&test_dir, "projects/drafts/idea.md", "# Idea");
+        create_test_file(&test_dir, "projects/scratch.tmp.md", "# Scratch");
+
+        // Indexing a folder still reads the ignore files and Obsidian
+        // settings at the vault root.
+        let db = Database::new(&db_path).unwrap();
+        for dir in [test_dir.clone(), test_dir.join("projects")] {
+            index_directory(
+                &dir,
+                &db,
+                &IndexOptions::default(),
+                &ExtractOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                db.get_paths_under(&vault_name(&db), "").unwrap(),
+                vec!["archived.md", "note.md", "projects/plan.md"]
+            );
+        }
+
+        let changed = [
+            test_dir.join("projects/build.log.md"),
+            test_dir.join("projects/private/secret.md"),
+        ];
+        let report = index_paths(
+            &test_dir,
+            &db,
+            &changed,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.indexed(), 0);
+
+        // Files that become excluded are dropped from the index.
+        let exclude = IndexOptions {
+            exclude: vec!["archived.md".to_string()],
+            ..Default::default()
+        };
+        index_directory(&test_dir, &db, &exclude, &ExtractOptions::default()).unwrap();
+        assert_eq!(
+            db.get_paths_under(&vault_name(&db), "").unwrap(),
+            vec!["note.md", "projects/plan.md"]
+        );
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("Café".as_bytes()), ("Café".to_string(), None));
+        assert_eq!(decode(b"\xEF\xBB\xBF# Note"), ("# Note".to_string(), None));
+        assert_eq!(
+            decode(b"\xFF\xFEh\0i\0"),
+            ("hi".to_string(), Some("UTF-16LE"))
+        );
+        let (text, encoding) = decode(b"Caf\xE9 cr\xE8me br\xFBl\xE9e, d\xE9j\xE0 vu");
+        assert_eq!(text, "Café crème brûlée, déjà vu");
+        assert_eq!(encoding, Some("windows-1252"));
+    }
+
+    #[test]
+    fn test_index_goes_on_past_failed_files() {
+        let (test_dir, db_path) = create_test_directory();
+        let test_dir = test_dir.canonicalize().unwrap();
+
+        fs::write(test_dir.join("latin1.md"), b"# Caf\xE9\n\nCaf\xE9 cr\xE8me").unwrap();
+        create_test_file(&test_dir, "linked.md", "# Linked");
+        create_test_file(&test_dir, "note.md", "See [[linked]]");
+
+        let db = Database::new(&db_path).unwrap();
+        let report = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert!(report.errors.is_empty());
+        let results = db
+            .query("SELECT title FROM documents WHERE name = 'latin1'", "*", 10)
+            .unwrap();
+        assert_eq!(results, vec![vec!["Café".to_string()]]);
+        let errors = db.get_document_errors(&vault_name(&db)).unwrap();
+        let (path, errors) = errors.iter().next().unwrap();
+        assert!(path.ends_with("latin1.md"));
+        assert_eq!(errors[0].kind, "encoding");
+
+        // A link left dangling cannot be read, but it fails alone and its
+        // row stays until it is fixed or removed.
+        #[cfg(unix)]
+        {
+            let linked = test_dir.join("linked.md");
+            fs::remove_file(&linked).unwrap();
+            std::os::unix::fs::symlink(test_dir.join("missing.md"), &linked).unwrap();
+            create_test_file(&test_dir, "new.md", "# New");
+            let report = index_directory(
+                &test_dir,
+                &db,
+                &IndexOptions::default(),
+                &ExtractOptions::default(),
+            )
+            .unwrap();
+            assert_eq!(report.failed, 1);
+            assert_eq!(report.errors[0].path, "linked.md");
+            assert_eq!(report.errors[0].stage, Stage::Walk);
+            assert!(stored_mtime(&db, "linked.md").is_some());
+            assert!(stored_mtime(&db, "new.md").is_some());
+        }
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_index_returns_on_write_error() {
+        let (test_dir, db_path) = create_test_directory();
+        // More than a batch of mtimes and the channels between the walk,
+        // workers and writer hold.
+        let files = WRITE_BATCH + 100;
+        for i in 0..files {
+            create_test_file(&test_dir, &format!("note{}.md", i), "Same text");
+        }
+        let db = Database::new(&db_path).unwrap();
+        let index = IndexOptions {
+            jobs: 1,
+            ..Default::default()
+        };
+        index_directory(&test_dir, &db, &index, &ExtractOptions::default()).unwrap();
+
+        // Every file now looks touched, and `documents` takes no updates, so
+        // the writer fails on its first batch of mtimes.
+        let touched = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        for i in 0..files {
+            let file = fs::File::options()
+                .write(true)
+                .open(test_dir.join(format!("note{}.md", i)))
+                .unwrap();
+            file.set_modified(touched).unwrap();
+        }
+        db.execute_batch(
+            "DROP INDEX idx_mtime;
+             DROP INDEX idx_folder;
+             DROP INDEX idx_name;
+             ALTER TABLE documents RENAME TO documents_data;
+             CREATE VIEW documents AS SELECT * FROM documents_data;",
+        )
+        .unwrap();
+
+        let (done_tx, done_rx) = mpsc::channel();
+        let dir = test_dir.clone();
+        std::thread::spawn(move || {
+            let result = index_directory(&dir, &db, &index, &ExtractOptions::default());
+            done_tx.send(result.is_err()).unwrap();
+        });
+        let failed = done_rx.recv_timeout(Duration::from_secs(30));
+        assert_eq!(failed, Ok(true), "index_directory should return the error");
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_returns_insert_errors() {
+        let (test_dir, db_path) = create_test_directory();
+        create_test_file(&test_dir, "note.md", "# Note");
+        let db = Database::new(&db_path).unwrap();
+        index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+
+        // Not a key conflict, so the run fails with it rather than writing
+        // the new file again on its own.
+        create_test_file(&test_dir, "snippet.md", "```sh\nls\n```");
+        db.execute_batch("DROP TABLE code_blocks").unwrap();
+        let err = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("code_blocks"));
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_linked_folder() {
+        let (test_dir, db_path) = create_test_directory();
+        let (shared, _) = create_test_directory();
+        let test_dir = test_dir.canonicalize().unwrap();
+
+        create_test_file(&test_dir, "note.md", "See [[plan]]");
+        create_test_file(&shared, "plan.md", "# Plan");
+        std::os::unix::fs::symlink(&shared, test_dir.join("shared")).unwrap();
+
+        // Files in a linked folder are indexed where the link puts them.
+        let db = Database::new(&db_path).unwrap();
+        let report = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            db.get_paths_under(&vault_name(&db), "").unwrap(),
+            vec!["note.md", "shared/plan.md"]
+        );
+
+        create_test_file(&shared, "plan.md", "# Plan, edited");
+        let report = index_paths(
+            &test_dir,
+            &db,
+            &[test_dir.join("shared/plan.md")],
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(report.updated, 1);
+
+        cleanup(&test_dir, &db_path);
+        let _ = fs::remove_dir_all(&shared);
+    }
+
+    #[test]
+    fn test_index_report() {
+        let (test_dir, db_path) = create_test_directory();
+
+        create_test_file(&test_dir, "a.md", "# A");
+        create_test_file(&test_dir, "b.md", "# B");
+        let c = create_test_file(&test_dir, "c.md", "# C");
+        let d = create_test_file(&test_dir, "d.md", "# D");
+
+        let db = Database::new(&db_path).unwrap();
+        let report = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!((report.added, report.updated, report.unchanged), (4, 0, 0));
+        assert!(report.durations.total >= report.durations.scan);
+
+        create_test_file(&test_dir, "a.md", "# A, edited");
+        create_test_file(&test_dir, "e.md", "# E");
+        fs::rename(&c, test_dir.join("moved.md")).unwrap();
+        fs::remove_file(&d).unwrap();
+        let dry_run = IndexOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let expected = |report: &IndexReport| {
+            assert_eq!(report.added, 1);
+            assert_eq!(report.updated, 1);
+            assert_eq!(report.unchanged, 1);
+            assert_eq!(report.renamed, 1);
+            assert_eq!(report.deleted, 1);
+            assert_eq!(report.failed, 0);
+        };
+        expected(&index_directory(&test_dir, &db, &dry_run, &ExtractOptions::default()).unwrap());
+        let report = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        expected(&report);
+        assert_eq!(report.indexed(), 2);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["added"], 1);
+        assert_eq!(json["deleted"], 1);
+        assert!(json["durations"]["total_ms"].is_u64());
+        assert_eq!(json["errors"], serde_json::json!([]));
+
+        let report = index_directory(
+            &test_dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert!(!report.has_changes());
+        assert_eq!(report.unchanged, 4);
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_stores_paths_relative_to_the_vault() {
+        let (test_dir, db_path) = create_test_directory();
+        let name = test_dir.file_name().unwrap().to_string_lossy().to_string();
+        fs::create_dir_all(test_dir.join("projects")).unwrap();
+        create_test_file(&test_dir, "projects/plan.md", "# Plan");
+        create_test_file(&test_dir, "inbox.md", "See [[plan]]");
+
+        let db = Database::new(&db_path).unwrap();
+        let index = IndexOptions::default();
+        let report = index_directory(&test_dir, &db, &index, &ExtractOptions::default()).unwrap();
+        assert_eq!(report.vault, name);
+        let vault = Vault {
+            name: name.clone(),
+            root: test_dir.canonicalize().unwrap(),
+        };
+        assert_eq!(db.get_vaults().unwrap(), vec![vault.clone()]);
+        let sql = "SELECT vault, path, folder, to_json(backlinks) FROM documents ORDER BY path";
+        let expected = vec![
+            vec![name.as_str(), "inbox.md", "", "[]"],
+            vec![
+                name.as_str(),
+                "projects/plan.md",
+                "projects",
+                r#"["inbox.md"]"#,
+            ],
+        ];
+        assert_eq!(db.query(sql, "*", 10).unwrap(), expected);
+
+        // A subfolder is indexed as part of the vault around it.
+        create_test_file(&test_dir, "projects/next.md", "# Next");
+        index_directory(
+            &test_dir.join("projects"),
+            &db,
+            &index,
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(db.get_vaults().unwrap(), vec![vault]);
+        assert!(stored_mtime(&db, "projects/next.md").is_some());
+
+        // A vault moved to a folder of the same name keeps its index.
+        let parent = test_dir.with_extension("moved");
+        let _ = fs::remove_dir_all(&parent);
+        fs::create_dir_all(&parent).unwrap();
+        let moved = parent.join(&name);
+        fs::rename(&test_dir, &moved).unwrap();
+        let report = index_directory(&moved, &db, &index, &ExtractOptions::default()).unwrap();
+        assert_eq!(report.unchanged, 3);
+        assert!(!report.has_changes());
+        let vaults = db.get_vaults().unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(vaults[0].root, moved.canonicalize().unwrap());
+
+        cleanup(&parent, &db_path);
+    }
+
+    #[test]
+    fn test_index_several_vaults() {
+        let (test_dir, db_path) = create_test_directory();
+        let (team, personal) = (test_dir.join("team"), test_dir.join("personal"));
+        fs::create_dir_all(&team).unwrap();
+        fs::create_dir_all(&personal).unwrap();
+        create_test_file(&team, "README.md", "See [[plan]]");
+        create_test_file(&team, "plan.md", "# Team plan");
+        create_test_file(&personal, "README.md", "My [[plan]]");
+
+        let db = Database::new(&db_path).unwrap();
+        let in_vault = |name: &str| IndexOptions {
+            vault: Some(name.to_string()),
+            ..Default::default()
+        };
+        for (dir, name) in [(&team, "team"), (&personal, "mine")] {
+            index_directory(dir, &db, &in_vault(name), &ExtractOptions::default()).unwrap();
+        }
+        let names: Vec<String> = db
+            .get_vaults()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(names, vec!["mine", "team"]);
+
+        // The same path in two vaults, with links resolved within each.
+        let sql = "SELECT vault, path, to_json(backlinks) FROM documents ORDER BY vault, path";
+        let expected = vec![
+            vec!["mine", "README.md", "[]"],
+            vec!["team", "README.md", "[]"],
+            vec!["team", "plan.md", r#"["README.md"]"#],
+        ];
+        assert_eq!(db.query(sql, "*", 10).unwrap(), expected);
+        let sql = crate::query::build_sql(
+            &crate::query::within_vault("name == 'README'", "team"),
+            "file.vault, path",
+        )
+        .unwrap();
+        assert_eq!(
+            db.query(&sql, "*", 10).unwrap(),
+            vec![vec!["team", "README.md"]]
+        );
+
+        // Indexing one vault leaves the other alone.
+        fs::remove_file(personal.join("README.md")).unwrap();
+        let report = index_directory(
+            &personal,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!((report.vault.as_str(), report.deleted), ("mine", 1));
+        assert_eq!(
+            db.get_paths_under("team", "").unwrap(),
+            vec!["README.md", "plan.md"]
+        );
+
+        // A new folder named like a vault still in place needs its own name.
+        let other = test_dir.join("other/team");
+        fs::create_dir_all(&other).unwrap();
+        assert!(
+            index_directory(
+                &other,
+                &db,
+                &IndexOptions::default(),
+                &ExtractOptions::default()
+            )
+            .is_err()
+        );
+
+        // Nor does naming it move the vault there, unless asked to.
+        let err = index_directory(&other, &db, &in_vault("team"), &ExtractOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("--move-vault"));
+        assert_eq!(
+            db.get_vaults().unwrap()[1].root,
+            team.canonicalize().unwrap()
+        );
+        let move_vault = IndexOptions {
+            move_vault: true,
+            ..in_vault("team")
+        };
+        index_directory(&other, &db, &move_vault, &ExtractOptions::default()).unwrap();
+        assert_eq!(
+            db.get_vaults().unwrap()[1].root,
+            other.canonicalize().unwrap()
+        );
+
+        cleanup(&test_dir, &db_path);
+    }
+
+    #[test]
+    fn test_index_vault_named_after_any_directory() {
+        let (test_dir, db_path) = create_test_directory();
+        let dir = test_dir.join("R&D (Tom's)");
+        fs::create_dir_all(&dir).unwrap();
+        create_test_file(&dir, "plan.md", "# Plan");
+
+        let db = Database::new(&db_path).unwrap();
+        let report = index_directory(
+            &dir,
+            &db,
+            &IndexOptions::default(),
+            &ExtractOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.vault, "R_D _Tom_s_");
+        let sql = crate::query::build_sql(
+            &crate::query::within_vault("name == 'plan'", &report.vault),
+            "path",
+        )
+        .unwrap();
+        assert_eq!(db.query(&sql, "*", 10).unwrap(), vec![vec!["plan.md"]]);
+
+        // A name given with --vault is checked, not rewritten.
+        let options = IndexOptions {
+            vault: Some("R&D".to_string()),
+            ..Default::default()
+        };
+        let err = index_directory(&dir, &db, &options, &ExtractOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("--vault"));
+
+        cleanup(&test_dir, &db_path);
+    }
 }
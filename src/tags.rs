@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Returns every level of a nested tag, outermost first, so
+/// `projects/mobile/ios` yields `projects`, `projects/mobile` and
+/// `projects/mobile/ios`.
+pub fn tag_ancestors(tag: &str) -> Vec<String> {
+    let segments: Vec<&str> = tag.split('/').filter(|s| !s.is_empty()).collect();
+    (1..=segments.len())
+        .map(|depth| segments[..depth].join("/"))
+        .collect()
+}
+
+/// Number of documents carrying each tag, lowercased since tags are
+/// case-insensitive.
+pub fn tag_counts(tag_map: &HashMap<String, Vec<String>>) -> BTreeMap<String, usize> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for tags in tag_map.values() {
+        let unique: BTreeSet<String> = tags.iter().map(|t| t.to_lowercase()).collect();
+        for tag in unique {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+    counts
+}
+
+#[derive(Debug, Default)]
+pub struct TagNode {
+    /// Documents tagged with this tag or any of its descendants.
+    pub count: usize,
+    pub children: BTreeMap<String, TagNode>,
+}
+
+#[derive(Debug, Default)]
+pub struct TagTree {
+    pub roots: BTreeMap<String, TagNode>,
+}
+
+impl TagTree {
+    /// Builds the tree from a `path -> tags` map, counting each document once
+    /// per level even when several of its tags share a parent. Tags are
+    /// lowercased, so `#Projects` and `#projects` share a node.
+    pub fn build(tag_map: &HashMap<String, Vec<String>>) -> Self {
+        let mut tree = TagTree::default();
+
+        for tags in tag_map.values() {
+            let levels: BTreeSet<String> = tags
+                .iter()
+                .flat_map(|t| tag_ancestors(&t.to_lowercase()))
+                .collect();
+            for level in &levels {
+                let segments: Vec<&str> = level.split('/').collect();
+                Self::count_level(&mut tree.roots, &segments);
+            }
+        }
+
+        tree
+    }
+
+    fn count_level(nodes: &mut BTreeMap<String, TagNode>, segments: &[&str]) {
+        if let Some((first, rest)) = segments.split_first() {
+            let node = nodes.entry(first.to_string()).or_default();
+            if rest.is_empty() {
+                node.count += 1;
+            } else {
+                Self::count_level(&mut node.children, rest);
+            }
+        }
+    }
+
+    /// Renders the tree as indented `name (count)` lines.
+    pub fn render(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        Self::render_level(&self.roots, 0, &mut lines);
+        lines
+    }
+
+    fn render_level(nodes: &BTreeMap<String, TagNode>, depth: usize, lines: &mut Vec<String>) {
+        for (name, node) in nodes {
+            lines.push(format!("{}{} ({})", "  ".repeat(depth), name, node.count));
+            Self::render_level(&node.children, depth + 1, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_map(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(path, tags)| {
+                (
+                    path.to_string(),
+                    tags.iter().map(|t| t.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tag_ancestors() {
+        assert_eq!(tag_ancestors("todo"), vec!["todo"]);
+        assert_eq!(
+            tag_ancestors("projects/mobile/ios"),
+            vec!["projects", "projects/mobile", "projects/mobile/ios"]
+        );
+        assert_eq!(tag_ancestors("a//b/"), vec!["a", "a/b"]);
+    }
+
+    #[test]
+    fn test_tag_counts() {
+        let map = tag_map(&[("a.md", &["todo", "todo", "x/y"]), ("b.md", &["todo"])]);
+        let counts = tag_counts(&map);
+        assert_eq!(counts["todo"], 2);
+        assert_eq!(counts["x/y"], 1);
+        assert!(!counts.contains_key("x"));
+
+        let map = tag_map(&[("a.md", &["Project", "project"]), ("b.md", &["PROJECT"])]);
+        assert_eq!(tag_counts(&map)["project"], 2);
+    }
+
+    #[test]
+    fn test_tag_tree_counts_documents_per_level() {
+        let map = tag_map(&[
+            ("a.md", &["projects/mobile", "projects/web"]),
+            ("b.md", &["projects/mobile/ios"]),
+            ("c.md", &["projects", "todo"]),
+        ]);
+        let tree = TagTree::build(&map);
+
+        let projects = &tree.roots["projects"];
+        assert_eq!(projects.count, 3);
+        assert_eq!(projects.children["mobile"].count, 2);
+        assert_eq!(projects.children["mobile"].children["ios"].count, 1);
+        assert_eq!(projects.children["web"].count, 1);
+        assert_eq!(tree.roots["todo"].count, 1);
+
+        assert_eq!(
+            tree.render(),
+            vec![
+                "projects (3)",
+                "  mobile (2)",
+                "    ios (1)",
+                "  web (1)",
+                "todo (1)",
+            ]
+        );
+    }
+}
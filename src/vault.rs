@@ -0,0 +1,125 @@
+use std::path::{Component, Path, PathBuf};
+
+/// A directory indexed into the database under a name. Paths are stored
+/// relative to its root with `/` between folders, so a database still works
+/// once the vault is moved or synced to another machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vault {
+    pub name: String,
+    pub root: PathBuf,
+}
+
+impl Vault {
+    /// `path`, an absolute path, relative to the root: `""` for the root
+    /// itself, `None` outside it.
+    pub fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let parts: Vec<String> = relative
+            .components()
+            .map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        Some(parts.join("/"))
+    }
+
+    /// The absolute path of `path`, a path relative to the root.
+    pub fn absolute(&self, path: &str) -> PathBuf {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self.root.clone(), |absolute, part| absolute.join(part))
+    }
+}
+
+/// Vault names end up in queries as `file.vault == '<name>'`, so they keep
+/// to letters, digits, spaces, `-`, `_` and `.`.
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.')
+}
+
+/// Checks a name given with `--vault`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() || !name.chars().all(is_name_char) {
+        return Err(format!(
+            "--vault name '{}' must use only letters, digits, spaces, -, _ and .",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// The name of a new vault named after its directory, with each character
+/// a vault name can't hold replaced by `_`: `R&D` becomes `R_D`.
+pub fn name_from(dir_name: &str) -> String {
+    let name: String = dir_name
+        .chars()
+        .map(|c| if is_name_char(c) { c } else { '_' })
+        .collect();
+    if name.trim().is_empty() {
+        "vault".to_string()
+    } else {
+        name
+    }
+}
+
+/// The folder a relative path is in, `""` at the root.
+pub fn folder_of(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(folder, _)| folder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_and_absolute_paths() {
+        let vault = Vault {
+            name: "notes".to_string(),
+            root: PathBuf::from("/home/me/notes"),
+        };
+
+        assert_eq!(
+            vault.relative(Path::new("/home/me/notes/projects/plan.md")),
+            Some("projects/plan.md".to_string())
+        );
+        assert_eq!(
+            vault.relative(Path::new("/home/me/notes")),
+            Some(String::new())
+        );
+        assert_eq!(vault.relative(Path::new("/home/me/notes2/a.md")), None);
+        assert_eq!(vault.relative(Path::new("/home/me/notes/../a.md")), None);
+
+        assert_eq!(
+            vault.absolute("projects/plan.md"),
+            PathBuf::from("/home/me/notes/projects/plan.md")
+        );
+        assert_eq!(vault.absolute(""), PathBuf::from("/home/me/notes"));
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("notes").is_ok());
+        assert!(check_name("Team Vault_2.0").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("it's").is_err());
+        assert!(check_name("a/b").is_err());
+        assert!(check_name("R&D").unwrap_err().contains("--vault"));
+    }
+
+    #[test]
+    fn test_name_from() {
+        assert_eq!(name_from("notes"), "notes");
+        assert_eq!(name_from("Notes (work)"), "Notes _work_");
+        assert_eq!(name_from("Tom's vault"), "Tom_s vault");
+        assert_eq!(name_from("R&D"), "R_D");
+        assert_eq!(name_from("   "), "vault");
+        assert!(check_name(&name_from("a'b\"c")).is_ok());
+    }
+
+    #[test]
+    fn test_folder_of() {
+        assert_eq!(folder_of("projects/2024/plan.md"), "projects/2024");
+        assert_eq!(folder_of("inbox.md"), "");
+    }
+}
@@ -0,0 +1,234 @@
+use crate::db::Database;
+use crate::extractor::ExtractOptions;
+use crate::ignore_rules::IgnoreRules;
+use crate::scanner::{self, IndexOptions};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How long a vault has to be quiet before changes are indexed, so a save
+/// that touches a file several times is indexed once.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Filesystem notifications for a vault, turned into index updates.
+pub struct VaultWatcher {
+    dir: PathBuf,
+    root: PathBuf,
+    rules: IgnoreRules,
+    events: Receiver<notify::Result<Event>>,
+    debounce: Duration,
+    // Notifications stop when it is dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl VaultWatcher {
+    /// Starts watching `dir`, in the vault at `root`, and everything under
+    /// it.
+    pub fn new(
+        dir: &Path,
+        root: &Path,
+        index: &IndexOptions,
+        debounce: Duration,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let dir = dir.canonicalize()?;
+        let rules = IgnoreRules::load(root, &dir, &index.include, &index.exclude)?;
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(&dir, RecursiveMode::Recursive)?;
+        Ok(VaultWatcher {
+            dir,
+            root: root.to_path_buf(),
+            rules,
+            events,
+            debounce,
+            _watcher: watcher,
+        })
+    }
+
+    /// Waits up to `timeout`, or for good when `None`, for a change, then
+    /// until no more arrive for the debounce time, and indexes the files
+    /// touched. Returns whether anything was indexed.
+    ///
+    /// Indexing errors are reported and the watch goes on, since a file
+    /// caught mid-write is indexed again on its next change. Only a failing
+    /// watcher is an error.
+    pub fn update(
+        &mut self,
+        db: &Database,
+        index: &IndexOptions,
+        options: &ExtractOptions,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut next = match timeout {
+            Some(timeout) => self.events.recv_timeout(timeout),
+            None => self
+                .events
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let mut paths: BTreeSet<PathBuf> = BTreeSet::new();
+        let mut rescan = false;
+        loop {
+            match next {
+                Ok(Ok(event)) if event.need_rescan() => rescan = true,
+                // Files being read, by us among others.
+                Ok(Ok(Event {
+                    kind: EventKind::Access(_),
+                    ..
+                })) => {}
+                Ok(Ok(event)) => paths.extend(event.paths),
+                Ok(Err(err)) => {
+                    println!("Warning: {}", err);
+                    rescan = true;
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err("file watcher stopped".into()),
+            }
+            next = self.events.recv_timeout(self.debounce);
+        }
+
+        if paths.iter().any(|path| self.rules.is_rules_file(path)) {
+            self.rules = IgnoreRules::load(&self.root, &self.dir, &index.include, &index.exclude)?;
+            rescan = true;
+        }
+        let result = if rescan {
+            scanner::index_directory(&self.dir, db, index, options)
+        } else {
+            // Drops writes to a database kept in the vault, such as
+            // `.mdb/mdb.duckdb`, which would otherwise wake the watcher
+            // after every update.
+            paths.retain(|path| !self.rules.is_ignored_with_parents(path, path.is_dir()));
+            if paths.is_empty() {
+                return Ok(false);
+            }
+            let paths: Vec<PathBuf> = paths.into_iter().collect();
+            scanner::index_paths(&self.dir, db, &paths, index, options)
+        };
+        match result {
+            Ok(report) => {
+                if report.has_changes() {
+                    println!(
+                        "Indexed {} files, renamed {}, removed {}",
+                        report.indexed(),
+                        report.renamed,
+                        report.deleted
+                    );
+                }
+                report.print_errors();
+            }
+            Err(err) => println!("Error: {}", err),
+        }
+        Ok(true)
+    }
+}
+
+/// Indexes `dir`, then keeps the index up to date as files are created,
+/// changed, moved and deleted, until the process is stopped.
+pub fn watch_directory(
+    dir: &Path,
+    db: &Database,
+    index: &IndexOptions,
+    options: &ExtractOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Started first so nothing changed during the initial scan is missed.
+    let vault = scanner::open_vault(db, dir, index)?;
+    let mut watcher = VaultWatcher::new(dir, &vault.root, index, DEBOUNCE)?;
+    let report = scanner::index_directory(dir, db, index, options)?;
+    scanner::print_report(db, &report, index)?;
+    println!("Watching {} for changes", dir.display());
+    loop {
+        watcher.update(db, index, options, None)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    static TEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    fn create_test_directory() -> (PathBuf, PathBuf) {
+        let temp_dir = std::env::temp_dir();
+        let unique_id = TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let test_dir = temp_dir.join(format!("test_watcher_{}_{}", std::process::id(), unique_id));
+        let db_path = temp_dir.join(format!(
+            "test_watcher_db_{}_{}.duckdb",
+            std::process::id(),
+            unique_id
+        ));
+        cleanup(&test_dir, &db_path);
+        fs::create_dir_all(&test_dir).unwrap();
+        (test_dir.canonicalize().unwrap(), db_path)
+    }
+
+    fn cleanup(test_dir: &Path, db_path: &Path) {
+        let _ = fs::remove_dir_all(test_dir);
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(db_path.with_extension("duckdb.wal"));
+    }
+
+    fn backlinks(db: &Database, name: &str) -> String {
+        let sql = format!("SELECT backlinks FROM documents WHERE name = '{}'", name);
+        db.query(&sql, "*", 10).unwrap()[0][0].clone()
+    }
+
+    /// Applies changes as they are noticed until `done` holds.
+    fn wait_for(watcher: &mut VaultWatcher, db: &Database, done: impl Fn(&Database) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done(db) {
+            assert!(Instant::now() < deadline, "index did not catch up");
+            watcher
+                .update(
+                    db,
+                    &IndexOptions::default(),
+                    &ExtractOptions::default(),
+                    Some(Duration::from_millis(200)),
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_watch_updates_index() {
+        let (test_dir, db_path) = create_test_directory();
+        fs::write(test_dir.join("a.md"), "# A").unwrap();
+        fs::write(test_dir.join("b.md"), "See [[a]]").unwrap();
+
+        let db = Database::new(&db_path).unwrap();
+        let index = IndexOptions::default();
+        let vault = scanner::index_directory(&test_dir, &db, &index, &ExtractOptions::default())
+            .unwrap()
+            .vault;
+        let mut watcher =
+            VaultWatcher::new(&test_dir, &test_dir, &index, Duration::from_millis(100)).unwrap();
+        assert_eq!(backlinks(&db, "a"), r#"["b.md"]"#);
+
+        // A new note adds to the backlinks of the note it links to.
+        fs::write(test_dir.join("c.md"), "Also [[a]]").unwrap();
+        wait_for(&mut watcher, &db, |db| backlinks(db, "a").contains("c.md"));
+
+        // Editors that save by renaming a temporary file into place.
+        fs::write(test_dir.join("b.md.tmp"), "No links").unwrap();
+        fs::rename(test_dir.join("b.md.tmp"), test_dir.join("b.md")).unwrap();
+        wait_for(&mut watcher, &db, |db| !backlinks(db, "a").contains("b.md"));
+
+        // A move keeps the row, and the backlinks pointing at it follow.
+        fs::rename(test_dir.join("c.md"), test_dir.join("d.md")).unwrap();
+        wait_for(&mut watcher, &db, |db| backlinks(db, "a") == r#"["d.md"]"#);
+
+        fs::remove_file(test_dir.join("d.md")).unwrap();
+        wait_for(&mut watcher, &db, |db| backlinks(db, "a") == "[]");
+        assert_eq!(
+            db.get_paths_under(&vault, "").unwrap(),
+            vec!["a.md", "b.md"]
+        );
+
+        drop(db);
+        cleanup(&test_dir, &db_path);
+    }
+}